@@ -0,0 +1,297 @@
+use crate::extra::attach_extras;
+use crate::{de, Manifest};
+use std::cell::Cell;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+/// A deserialization failure located within the manifest source.
+#[derive(Debug)]
+pub struct ParseError(Box<Located>);
+
+#[derive(Debug)]
+struct Located {
+    path: PathBuf,
+    offset: usize,
+    line: usize,
+    column: usize,
+    element: Option<String>,
+    attribute: Option<String>,
+    source_line: String,
+    // Character range within `source_line` to underline.
+    span: (usize, usize),
+    err: de::DeError,
+}
+
+impl ParseError {
+    pub fn path(&self) -> &Path {
+        &self.0.path
+    }
+    /// Byte offset at which the deserializer stopped.
+    pub fn offset(&self) -> usize {
+        self.0.offset
+    }
+    /// One based line of the offending element or attribute.
+    pub fn line(&self) -> usize {
+        self.0.line
+    }
+    /// One based column, counted in characters.
+    pub fn column(&self) -> usize {
+        self.0.column
+    }
+    pub fn element(&self) -> Option<&str> {
+        self.0.element.as_deref()
+    }
+    pub fn attribute(&self) -> Option<&str> {
+        self.0.attribute.as_deref()
+    }
+    pub fn de_error(&self) -> &de::DeError {
+        &self.0.err
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.0.line.to_string().len());
+        writeln!(f, "error: {}", self.0.err)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter,
+            self.0.path.display(),
+            self.0.line,
+            self.0.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.0.line, self.0.source_line)?;
+        let (start, end) = self.0.span;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(start),
+            "^".repeat((end - start).max(1))
+        )?;
+        match (&self.0.element, &self.0.attribute) {
+            (Some(element), Some(attribute)) => {
+                write!(f, " attribute `{}` of <{}>", attribute, element)
+            }
+            (Some(element), None) => write!(f, " in <{}>", element),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.0.err)
+    }
+}
+
+/// Counts the bytes the deserializer has consumed.
+struct PositionReader<'a, R> {
+    inner: R,
+    position: &'a Cell<usize>,
+}
+
+impl<R: BufRead> Read for PositionReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position.set(self.position.get() + n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for PositionReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        self.position.set(self.position.get() + amt);
+        self.inner.consume(amt)
+    }
+}
+
+/// Deserializes `source`, attributing any error to a location within `path`.
+///
+/// Attributes and elements which are not modelled are kept in the `extra` of
+/// the element they belong to.
+pub fn parse_str(source: &str, path: &Path) -> Result<Manifest, ParseError> {
+    let position = Cell::new(0);
+    let reader = PositionReader {
+        inner: source.as_bytes(),
+        position: &position,
+    };
+    let mut manifest: Manifest =
+        de::from_reader(reader).map_err(|err| locate(source, path, position.get(), err))?;
+    attach_extras(&mut manifest, source, path);
+    Ok(manifest)
+}
+
+struct Tag {
+    name: String,
+    start: usize,
+    end: usize,
+}
+
+/// Finds the element being deserialized when `offset` bytes had been read,
+/// that is the innermost open element, or the one which was just closed.
+fn current_tag(source: &str, offset: usize) -> Option<Tag> {
+    let source = &source[..offset.min(source.len())];
+    let mut stack: Vec<Tag> = Vec::new();
+    let mut last = None;
+    let mut pos = 0;
+    while let Some(found) = source[pos..].find('<') {
+        let start = pos + found;
+        let rest = &source[start..];
+        let close = if rest.starts_with("<!--") {
+            rest.find("-->").map(|i| i + 3)
+        } else {
+            rest.find('>').map(|i| i + 1)
+        };
+        let end = match close {
+            Some(len) => start + len,
+            None => source.len(),
+        };
+        let text = &source[start..end];
+        pos = end;
+        if text.starts_with("<!") || text.starts_with("<?") {
+            continue;
+        }
+        if let Some(name) = text.strip_prefix("</") {
+            let name = name.trim_end_matches('>').trim();
+            if let Some(i) = stack.iter().rposition(|tag| tag.name == name) {
+                stack.truncate(i + 1);
+                last = stack.pop();
+            }
+            continue;
+        }
+        let name: String = text[1..]
+            .chars()
+            .take_while(|c| !c.is_whitespace() && *c != '/' && *c != '>')
+            .collect();
+        let tag = Tag { name, start, end };
+        if text.ends_with("/>") || close.is_none() {
+            last = Some(tag);
+        } else {
+            stack.push(tag);
+            last = None;
+        }
+    }
+    last.or_else(|| stack.pop())
+}
+
+/// An attribute as written in a start tag.
+pub(crate) struct Attribute<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) value: &'a str,
+    // Byte range of `name="value"` within the tag.
+    pub(crate) span: (usize, usize),
+}
+
+pub(crate) fn attributes(tag: &str) -> Vec<Attribute<'_>> {
+    let mut attributes = Vec::new();
+    let mut pos = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    while let Some(eq) = tag[pos..].find('=') {
+        let name = tag[pos..pos + eq].trim();
+        let name_start = pos + tag[pos..].find(name).unwrap_or(0);
+        let rest = tag[pos + eq + 1..].trim_start();
+        let quote = match rest.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => break,
+        };
+        let value_start = tag.len() - rest.len() + 1;
+        let value_end = match tag[value_start..].find(quote) {
+            Some(len) => value_start + len,
+            None => break,
+        };
+        attributes.push(Attribute {
+            name,
+            value: &tag[value_start..value_end],
+            span: (name_start, value_end + 1),
+        });
+        pos = value_end + 1;
+    }
+    attributes
+}
+
+/// The name of the attribute the error message refers to, along with its
+/// span when it is present in the tag.
+fn offending_attribute(
+    err: &de::DeError,
+    attributes: &[Attribute],
+) -> Option<(String, Option<(usize, usize)>)> {
+    let message = err.to_string();
+    if let Some(field) = message.strip_prefix("missing field `") {
+        return Some((field.trim_end_matches('`').to_string(), None));
+    }
+    if let Some(at) = message.find("attribute `") {
+        let name = &message[at + "attribute `".len()..];
+        let name = &name[..name.find('`').unwrap_or(name.len())];
+        let span = attributes.iter().find(|a| a.name == name).map(|a| a.span);
+        return Some((name.to_string(), span));
+    }
+    let quoted = match err {
+        de::DeError::InvalidBoolean(value) => Some(value.clone()),
+        _ => ["'", "\"", "`"].iter().find_map(|q| {
+            let start = message.find(q)? + 1;
+            let len = message[start..].find(q)?;
+            Some(message[start..start + len].to_string())
+        }),
+    };
+    let candidates: Vec<_> = match (&quoted, err) {
+        (Some(quoted), _) => attributes.iter().filter(|a| a.value == quoted).collect(),
+        (None, de::DeError::Int(_)) => attributes
+            .iter()
+            .filter(|a| a.value.parse::<i64>().is_err())
+            .collect(),
+        _ => Vec::new(),
+    };
+    match candidates.as_slice() {
+        [attribute] => Some((attribute.name.to_string(), Some(attribute.span))),
+        _ => None,
+    }
+}
+
+fn locate(source: &str, path: &Path, offset: usize, err: de::DeError) -> ParseError {
+    let tag = current_tag(source, offset);
+    let (element, attribute, (start, end)) = match tag {
+        Some(tag) => {
+            let name_span = (tag.start + 1, tag.start + 1 + tag.name.len());
+            match offending_attribute(&err, &attributes(&source[tag.start..tag.end])) {
+                Some((name, Some((start, end)))) => (
+                    Some(tag.name),
+                    Some(name),
+                    (tag.start + start, tag.start + end),
+                ),
+                Some((name, None)) => (Some(tag.name), Some(name), name_span),
+                None => (Some(tag.name), None, name_span),
+            }
+        }
+        None => {
+            let offset = offset.min(source.len());
+            (None, None, (offset, offset))
+        }
+    };
+
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let column = source[line_start..start].chars().count();
+    let width = source[start..end.min(line_end)].chars().count();
+    ParseError(Box::new(Located {
+        path: path.to_path_buf(),
+        offset,
+        line: source[..start].matches('\n').count() + 1,
+        column: column + 1,
+        element,
+        attribute,
+        // Tabs are replaced so that the underline lines up.
+        source_line: source[line_start..line_end]
+            .trim_end_matches('\r')
+            .replace('\t', " "),
+        span: (column, column + width),
+        err,
+    }))
+}
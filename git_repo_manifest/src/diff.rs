@@ -0,0 +1,253 @@
+use crate::groups::split_groups;
+use crate::{Manifest, Project, Remote};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// A difference between two manifests, as reported by [`Manifest::diff`].
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    ProjectAdded {
+        name: String,
+        path: String,
+    },
+    ProjectRemoved {
+        name: String,
+        path: String,
+    },
+    /// The same project checked out at a different path.
+    ProjectMoved {
+        name: String,
+        old_path: String,
+        new_path: String,
+    },
+    /// A different project name checked out at the same path.
+    ProjectRenamed {
+        path: String,
+        old_name: String,
+        new_name: String,
+    },
+    /// A change to a project's `revision`, `remote` or `groups`.
+    ProjectChanged {
+        name: String,
+        path: String,
+        attribute: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    RemoteAdded {
+        name: String,
+        fetch: String,
+    },
+    RemoteRemoved {
+        name: String,
+        fetch: String,
+    },
+    /// A change to a remote's `fetch`, `pushurl` or `review` URL.
+    RemoteChanged {
+        name: String,
+        attribute: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+fn or_none(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("(none)")
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Change::*;
+        match self {
+            ProjectAdded { name, path } => write!(f, "+ project {} at {}", name, path),
+            ProjectRemoved { name, path } => write!(f, "- project {} at {}", name, path),
+            ProjectMoved {
+                name,
+                old_path,
+                new_path,
+            } => write!(
+                f,
+                "~ project {} moved from {} to {}",
+                name, old_path, new_path
+            ),
+            ProjectRenamed {
+                path,
+                old_name,
+                new_name,
+            } => write!(
+                f,
+                "~ project at {} renamed from {} to {}",
+                path, old_name, new_name
+            ),
+            ProjectChanged {
+                name,
+                attribute,
+                old,
+                new,
+                ..
+            } => write!(
+                f,
+                "~ project {} {}: {} -> {}",
+                name,
+                attribute,
+                or_none(old),
+                or_none(new)
+            ),
+            RemoteAdded { name, fetch } => write!(f, "+ remote {} fetching {}", name, fetch),
+            RemoteRemoved { name, fetch } => write!(f, "- remote {} fetching {}", name, fetch),
+            RemoteChanged {
+                name,
+                attribute,
+                old,
+                new,
+            } => write!(
+                f,
+                "~ remote {} {}: {} -> {}",
+                name,
+                attribute,
+                or_none(old),
+                or_none(new)
+            ),
+        }
+    }
+}
+
+/// Groups are compared as sets, so reordering them is not a change.
+fn group_set(groups: &Option<String>) -> BTreeSet<&str> {
+    groups
+        .iter()
+        .flat_map(|groups| split_groups(groups))
+        .collect()
+}
+
+fn diff_project(old: &Project, new: &Project, changes: &mut Vec<Change>) {
+    let attributes = [
+        ("revision", &old.revision, &new.revision),
+        ("remote", &old.remote, &new.remote),
+        ("groups", &old.groups, &new.groups),
+    ];
+    for (attribute, old_value, new_value) in attributes.iter() {
+        let changed = if *attribute == "groups" {
+            group_set(old_value) != group_set(new_value)
+        } else {
+            old_value != new_value
+        };
+        if changed {
+            changes.push(Change::ProjectChanged {
+                name: new.name.clone(),
+                path: new.relpath().to_string(),
+                attribute: attribute.to_string(),
+                old: (*old_value).clone(),
+                new: (*new_value).clone(),
+            });
+        }
+    }
+}
+
+fn diff_remote(old: &Remote, new: &Remote, changes: &mut Vec<Change>) {
+    let attributes = [
+        ("fetch", Some(&old.fetch), Some(&new.fetch)),
+        ("pushurl", old.pushurl.as_ref(), new.pushurl.as_ref()),
+        ("review", old.review.as_ref(), new.review.as_ref()),
+    ];
+    for (attribute, old_value, new_value) in attributes.iter() {
+        if old_value != new_value {
+            changes.push(Change::RemoteChanged {
+                name: new.name.clone(),
+                attribute: attribute.to_string(),
+                old: old_value.cloned(),
+                new: new_value.cloned(),
+            });
+        }
+    }
+}
+
+impl Manifest {
+    /// Compares the projects and remotes of `self` to those of `new`.
+    ///
+    /// Projects are paired by name and path first, then by name alone, which
+    /// is reported as a move, and then by path alone, which is reported as a
+    /// rename. Attributes are compared as written, so call `resolve_projects`,
+    /// `resolve_fetch_urls` and `set_defaults` on both beforehand to compare the
+    /// effective values.
+    pub fn diff(&self, new: &Manifest) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        let mut old_unmatched: Vec<&Project> = self.projects.iter().collect();
+        let mut pairs = Vec::new();
+        let mut new_unmatched = Vec::new();
+        for project in &new.projects {
+            match old_unmatched
+                .iter()
+                .position(|p| p.name == project.name && p.relpath() == project.relpath())
+            {
+                Some(i) => pairs.push((old_unmatched.remove(i), project)),
+                None => new_unmatched.push(project),
+            }
+        }
+        let mut added = Vec::new();
+        for project in new_unmatched {
+            let by_name = old_unmatched.iter().position(|p| p.name == project.name);
+            let by_path = old_unmatched
+                .iter()
+                .position(|p| p.relpath() == project.relpath());
+            match (by_name, by_path) {
+                (Some(i), _) => {
+                    let old = old_unmatched.remove(i);
+                    changes.push(Change::ProjectMoved {
+                        name: project.name.clone(),
+                        old_path: old.relpath().to_string(),
+                        new_path: project.relpath().to_string(),
+                    });
+                    pairs.push((old, project));
+                }
+                (None, Some(i)) => {
+                    let old = old_unmatched.remove(i);
+                    changes.push(Change::ProjectRenamed {
+                        path: project.relpath().to_string(),
+                        old_name: old.name.clone(),
+                        new_name: project.name.clone(),
+                    });
+                    pairs.push((old, project));
+                }
+                (None, None) => added.push(project),
+            }
+        }
+        for project in old_unmatched {
+            changes.push(Change::ProjectRemoved {
+                name: project.name.clone(),
+                path: project.relpath().to_string(),
+            });
+        }
+        for project in added {
+            changes.push(Change::ProjectAdded {
+                name: project.name.clone(),
+                path: project.relpath().to_string(),
+            });
+        }
+        for (old, new) in pairs {
+            diff_project(old, new, &mut changes);
+        }
+
+        for remote in &self.remotes {
+            match new.remotes.iter().find(|r| r.name == remote.name) {
+                Some(new_remote) => diff_remote(remote, new_remote, &mut changes),
+                None => changes.push(Change::RemoteRemoved {
+                    name: remote.name.clone(),
+                    fetch: remote.fetch.clone(),
+                }),
+            }
+        }
+        for remote in &new.remotes {
+            if !self.remotes.iter().any(|r| r.name == remote.name) {
+                changes.push(Change::RemoteAdded {
+                    name: remote.name.clone(),
+                    fetch: remote.fetch.clone(),
+                });
+            }
+        }
+        changes
+    }
+}
@@ -0,0 +1,370 @@
+use crate::diagnostic::{attributes, parse_str, ParseError};
+use crate::Manifest;
+use quick_error::quick_error;
+use quick_xml::escape::{escape, unescape};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::fmt;
+use std::path::Path;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum DocumentError {
+        Xml(err: quick_xml::Error) {
+            from()
+            display("{}", err)
+            source(err)
+        }
+        NoRoot {
+            display("document has no root element")
+        }
+        NoParent {
+            display("the root element cannot be removed or given siblings")
+        }
+    }
+}
+
+/// Index of an element in document order.
+///
+/// Indices stay valid across attribute edits, inserting or removing an element
+/// renumbers the elements which follow it.
+pub type ElementId = usize;
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    // Byte range of the start tag, which is the whole element when empty.
+    start: (usize, usize),
+    // Byte range of the end tag.
+    end: Option<(usize, usize)>,
+    parent: Option<ElementId>,
+    children: Vec<ElementId>,
+}
+
+/// A manifest's XML source which can be edited in place.
+///
+/// Unlike deserializing into a `Manifest` and serializing it again, comments,
+/// attribute order, quoting and whitespace are kept, only the bytes of the
+/// edited elements and attributes change.
+#[derive(Debug)]
+pub struct Document {
+    text: String,
+    elements: Vec<Element>,
+}
+
+impl Document {
+    pub fn parse(text: String) -> Result<Document, DocumentError> {
+        let elements = scan(&text)?;
+        if elements.is_empty() {
+            return Err(DocumentError::NoRoot);
+        }
+        Ok(Document { text, elements })
+    }
+
+    fn rescan(&mut self) -> Result<(), DocumentError> {
+        self.elements = scan(&self.text)?;
+        Ok(())
+    }
+
+    /// The document's current text.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Deserializes the current text.
+    pub fn manifest(&self, path: &Path) -> Result<Manifest, ParseError> {
+        parse_str(&self.text, path)
+    }
+
+    pub fn root(&self) -> ElementId {
+        0
+    }
+
+    pub fn name(&self, element: ElementId) -> &str {
+        &self.elements[element].name
+    }
+
+    pub fn parent(&self, element: ElementId) -> Option<ElementId> {
+        self.elements[element].parent
+    }
+
+    pub fn children(&self, element: ElementId) -> &[ElementId] {
+        &self.elements[element].children
+    }
+
+    /// The children of the root element named `name`, e.g. every `project`.
+    pub fn elements<'a>(&'a self, name: &'a str) -> impl Iterator<Item = ElementId> + 'a {
+        self.children(self.root())
+            .iter()
+            .copied()
+            .filter(move |child| self.name(*child) == name)
+    }
+
+    /// The first child of the root named `name` with `attribute="value"`, such
+    /// as `find("project", "name", "kernel")`.
+    pub fn find(&self, name: &str, attribute: &str, value: &str) -> Option<ElementId> {
+        self.elements(name)
+            .find(|element| self.attribute(*element, attribute).as_deref() == Some(value))
+    }
+
+    fn start_tag(&self, element: ElementId) -> &str {
+        let (start, end) = self.elements[element].start;
+        &self.text[start..end]
+    }
+
+    /// The unescaped value of `attribute`.
+    pub fn attribute(&self, element: ElementId, attribute: &str) -> Option<String> {
+        let tag = self.start_tag(element);
+        let found = attributes(tag).into_iter().find(|a| a.name == attribute)?;
+        let value = unescape(found.value.as_bytes()).ok()?;
+        Some(String::from_utf8_lossy(&value).into_owned())
+    }
+
+    /// Every attribute of `element` in source order, with unescaped values and
+    /// the byte offset of each name.
+    pub(crate) fn attributes_with_offsets(
+        &self,
+        element: ElementId,
+    ) -> Vec<(String, String, usize)> {
+        let (tag_start, _) = self.elements[element].start;
+        attributes(self.start_tag(element))
+            .into_iter()
+            .map(|a| {
+                let value = unescape(a.value.as_bytes())
+                    .map(|value| String::from_utf8_lossy(&value).into_owned())
+                    .unwrap_or_else(|_| a.value.to_string());
+                (a.name.to_string(), value, tag_start + a.span.0)
+            })
+            .collect()
+    }
+
+    /// The source of `element`, from its start tag through its end tag.
+    pub fn source(&self, element: ElementId) -> &str {
+        let (start, end) = self.outer_span(element);
+        &self.text[start..end]
+    }
+
+    /// Byte offset of the start of `element`.
+    pub(crate) fn offset(&self, element: ElementId) -> usize {
+        self.elements[element].start.0
+    }
+
+    /// Replaces the value of `attribute`, adding it after the last attribute
+    /// when it is not present.
+    pub fn set_attribute(&mut self, element: ElementId, attribute: &str, value: &str) {
+        let (tag_start, _) = self.elements[element].start;
+        let tag = self.start_tag(element);
+        let escaped = escape_value(value);
+        let existing = attributes(tag).into_iter().find(|a| a.name == attribute);
+        let (range, replacement) = match existing {
+            Some(found) => {
+                let quote_end = tag_start + found.span.1 - 1;
+                ((quote_end - found.value.len(), quote_end), escaped)
+            }
+            None => {
+                let insert_at = tag_start
+                    + tag
+                        .trim_end_matches('>')
+                        .trim_end_matches('/')
+                        .trim_end()
+                        .len();
+                (
+                    (insert_at, insert_at),
+                    format!(" {}=\"{}\"", attribute, escaped),
+                )
+            }
+        };
+        self.splice(range, &replacement);
+    }
+
+    /// Removes `attribute` along with the whitespace preceding it, returning
+    /// whether it was present.
+    pub fn remove_attribute(&mut self, element: ElementId, attribute: &str) -> bool {
+        let (tag_start, _) = self.elements[element].start;
+        let tag = self.start_tag(element);
+        match attributes(tag).into_iter().find(|a| a.name == attribute) {
+            Some(found) => {
+                let whitespace = tag[..found.span.0].len() - tag[..found.span.0].trim_end().len();
+                let range = (
+                    tag_start + found.span.0 - whitespace,
+                    tag_start + found.span.1,
+                );
+                self.splice(range, "");
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes `element`, and the line it was on if nothing else was.
+    pub fn remove_element(&mut self, element: ElementId) -> Result<(), DocumentError> {
+        if self.elements[element].parent.is_none() {
+            return Err(DocumentError::NoParent);
+        }
+        let (start, end) = self.outer_span(element);
+        let line_start = start
+            - (self.text[..start].len() - self.text[..start].trim_end_matches([' ', '\t']).len());
+        let rest = &self.text[end..];
+        let trailing = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        let range = if (line_start == 0 || self.text[..line_start].ends_with('\n'))
+            && (rest[trailing..].starts_with('\n') || rest[trailing..].starts_with("\r\n"))
+        {
+            let newline = if rest[trailing..].starts_with('\n') {
+                1
+            } else {
+                2
+            };
+            (line_start, end + trailing + newline)
+        } else {
+            (start, end)
+        };
+        self.splice(range, "");
+        self.rescan()
+    }
+
+    /// Inserts the XML fragment `xml` on its own line after `sibling`, with the
+    /// same indentation.
+    pub fn insert_after(&mut self, sibling: ElementId, xml: &str) -> Result<(), DocumentError> {
+        if self.elements[sibling].parent.is_none() {
+            return Err(DocumentError::NoParent);
+        }
+        let (start, end) = self.outer_span(sibling);
+        let indent = self.indentation(start).to_string();
+        self.splice((end, end), &format!("\n{}{}", indent, xml));
+        self.rescan()
+    }
+
+    /// Appends the XML fragment `xml` as the last child of `parent`, indented
+    /// like its other children or one level deeper than `parent`.
+    pub fn append_child(&mut self, parent: ElementId, xml: &str) -> Result<(), DocumentError> {
+        if let Some(last) = self.elements[parent].children.last() {
+            return self.insert_after(*last, xml);
+        }
+        let (start, tag_end) = self.elements[parent].start;
+        let indent = self.indentation(start).to_string();
+        let child_indent = format!("{}{}", indent, self.indent_unit());
+        match self.elements[parent].end {
+            Some((end, _)) => {
+                let content = &self.text[tag_end..end];
+                let before = content.len() - content.trim_end().len();
+                self.splice(
+                    (end - before, end),
+                    &format!("\n{}{}\n{}", child_indent, xml, indent),
+                );
+            }
+            None => {
+                let tag = &self.text[start..tag_end];
+                let close = start
+                    + tag
+                        .trim_end_matches('>')
+                        .trim_end_matches('/')
+                        .trim_end()
+                        .len();
+                let name = self.elements[parent].name.clone();
+                self.splice(
+                    (close, tag_end),
+                    &format!(">\n{}{}\n{}</{}>", child_indent, xml, indent, name),
+                );
+            }
+        }
+        self.rescan()
+    }
+
+    fn outer_span(&self, element: ElementId) -> (usize, usize) {
+        let element = &self.elements[element];
+        (element.start.0, element.end.unwrap_or(element.start).1)
+    }
+
+    /// The whitespace between the start of the line and `offset`.
+    fn indentation(&self, offset: usize) -> &str {
+        let line_start = self.text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let prefix = &self.text[line_start..offset];
+        let indent = prefix.len() - prefix.trim_start().len();
+        &prefix[..indent]
+    }
+
+    /// The indentation of the root's children, relative to the root.
+    fn indent_unit(&self) -> String {
+        let root = &self.elements[self.root()];
+        let root_indent = self.indentation(root.start.0).len();
+        match root.children.first() {
+            Some(child) => {
+                let indent = self.indentation(self.elements[*child].start.0);
+                indent[root_indent.min(indent.len())..].to_string()
+            }
+            None => "  ".to_string(),
+        }
+    }
+
+    /// Replaces the bytes in `range`, shifting the spans that follow it.
+    fn splice(&mut self, range: (usize, usize), replacement: &str) {
+        self.text.replace_range(range.0..range.1, replacement);
+        let shift = |offset: &mut usize| {
+            if *offset >= range.1 {
+                *offset = *offset + replacement.len() - (range.1 - range.0);
+            } else if *offset > range.0 {
+                *offset = range.0 + replacement.len();
+            }
+        };
+        for element in &mut self.elements {
+            shift(&mut element.start.0);
+            shift(&mut element.start.1);
+            if let Some(end) = &mut element.end {
+                shift(&mut end.0);
+                shift(&mut end.1);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn escape_value(value: &str) -> String {
+    String::from_utf8_lossy(&escape(value.as_bytes())).into_owned()
+}
+
+/// Finds every element of `text` in document order.
+fn scan(text: &str) -> Result<Vec<Element>, DocumentError> {
+    let mut reader = Reader::from_str(text);
+    reader.check_end_names(true).check_comments(false);
+    let mut elements: Vec<Element> = Vec::new();
+    let mut open: Vec<ElementId> = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        let before = reader.buffer_position();
+        let event = reader.read_event(&mut buf)?;
+        let span = (before, reader.buffer_position());
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let id = elements.len();
+                let parent = open.last().copied();
+                if let Some(parent) = parent {
+                    elements[parent].children.push(id);
+                }
+                elements.push(Element {
+                    name: String::from_utf8_lossy(e.name()).into_owned(),
+                    start: span,
+                    end: None,
+                    parent,
+                    children: Vec::new(),
+                });
+                if let Event::Start(_) = event {
+                    open.push(id);
+                }
+            }
+            Event::End(_) => {
+                if let Some(id) = open.pop() {
+                    elements[id].end = Some(span);
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(elements)
+}
@@ -0,0 +1,177 @@
+use crate::{Manifest, Project, Remote};
+use quick_error::quick_error;
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum EditError {
+        UnknownProject(name: String) {
+            display("no project named {}", name)
+        }
+        UnknownRemote(name: String) {
+            display("no remote named {}", name)
+        }
+        NoRemote(project: String) {
+            display("project {} has no remote and there is no default remote", project)
+        }
+        DuplicateRemote(name: String) {
+            display("a remote named {} already exists", name)
+        }
+        PathInUse(path: String, project: String) {
+            display("path {} is already used by project {}", path, project)
+        }
+        RemoteInUse(remote: String) {
+            display("remote {} is still referenced", remote)
+        }
+        HooksProject(project: String) {
+            display("project {} provides the <repo-hooks>", project)
+        }
+    }
+}
+
+impl Manifest {
+    fn check_remote(&self, remote: &str) -> Result<(), EditError> {
+        if self.remotes.iter().any(|r| r.name == remote) {
+            Ok(())
+        } else {
+            Err(EditError::UnknownRemote(remote.to_string()))
+        }
+    }
+
+    fn projects_named(&mut self, name: &str) -> Result<Vec<&mut Project>, EditError> {
+        let projects: Vec<_> = self
+            .projects
+            .iter_mut()
+            .filter(|p| p.name == name)
+            .collect();
+        if projects.is_empty() {
+            Err(EditError::UnknownProject(name.to_string()))
+        } else {
+            Ok(projects)
+        }
+    }
+
+    /// Appends `project`, which must use a known remote and a path no other
+    /// project is checked out at.
+    pub fn add_project(&mut self, project: Project) -> Result<(), EditError> {
+        match &project.remote {
+            Some(remote) => self.check_remote(remote)?,
+            None => {
+                let default_remote = self.default.as_ref().and_then(|d| d.remote.as_ref());
+                match default_remote {
+                    Some(remote) => self.check_remote(remote)?,
+                    None => return Err(EditError::NoRemote(project.name)),
+                }
+            }
+        }
+        if let Some(existing) = self
+            .projects
+            .iter()
+            .find(|p| p.relpath() == project.relpath())
+        {
+            return Err(EditError::PathInUse(
+                project.relpath().to_string(),
+                existing.name.clone(),
+            ));
+        }
+        self.projects.push(project);
+        Ok(())
+    }
+
+    /// Removes every project named `name`, like `<remove-project name=...>`,
+    /// returning them.
+    pub fn remove_project(&mut self, name: &str) -> Result<Vec<Project>, EditError> {
+        if let Some(hooks) = &self.repo_hooks {
+            if hooks.in_project == name {
+                return Err(EditError::HooksProject(name.to_string()));
+            }
+        }
+        let (removed, kept) = self.projects.drain(..).partition(|p| p.name == name);
+        self.projects = kept;
+        if removed.is_empty() {
+            Err(EditError::UnknownProject(name.to_string()))
+        } else {
+            Ok(removed)
+        }
+    }
+
+    /// Sets the `revision` of every project named `name`.
+    pub fn set_project_revision(&mut self, name: &str, revision: &str) -> Result<(), EditError> {
+        for project in self.projects_named(name)? {
+            project.revision = Some(revision.to_string());
+        }
+        Ok(())
+    }
+
+    /// Points every project named `name` at the existing remote `remote`.
+    pub fn move_project_to_remote(&mut self, name: &str, remote: &str) -> Result<(), EditError> {
+        self.check_remote(remote)?;
+        for project in self.projects_named(name)? {
+            project.remote = Some(remote.to_string());
+        }
+        Ok(())
+    }
+
+    /// Appends `remote`, whose name must not be taken.
+    pub fn add_remote(&mut self, remote: Remote) -> Result<(), EditError> {
+        if self.check_remote(&remote.name).is_ok() {
+            return Err(EditError::DuplicateRemote(remote.name));
+        }
+        self.remotes.push(remote);
+        Ok(())
+    }
+
+    /// Removes the remote `name`, which no project, `<default>`,
+    /// `<superproject>` or `<submanifest>` may use.
+    pub fn remove_remote(&mut self, name: &str) -> Result<Remote, EditError> {
+        self.check_remote(name)?;
+        let referenced = self
+            .projects
+            .iter()
+            .map(|p| &p.remote)
+            .chain(self.extend_projects.iter().map(|p| &p.remote))
+            .chain(self.default.iter().map(|d| &d.remote))
+            .chain(self.superproject.iter().map(|s| &s.remote))
+            .chain(self.submanifests.iter().map(|s| &s.remote))
+            .any(|remote| remote.as_deref() == Some(name));
+        if referenced {
+            return Err(EditError::RemoteInUse(name.to_string()));
+        }
+        let index = self.remotes.iter().position(|r| r.name == name).unwrap();
+        Ok(self.remotes.remove(index))
+    }
+
+    /// Renames the remote `from` to `to`, along with every reference to it
+    /// from projects, `<extend-project>`s, `<default>`, `<superproject>` and
+    /// `<submanifest>`s.
+    pub fn rename_remote(&mut self, from: &str, to: &str) -> Result<(), EditError> {
+        self.check_remote(from)?;
+        if from != to && self.check_remote(to).is_ok() {
+            return Err(EditError::DuplicateRemote(to.to_string()));
+        }
+        let references = self
+            .remotes
+            .iter_mut()
+            .map(|r| &mut r.name)
+            .chain(self.projects.iter_mut().filter_map(|p| p.remote.as_mut()))
+            .chain(
+                self.extend_projects
+                    .iter_mut()
+                    .filter_map(|p| p.remote.as_mut()),
+            )
+            .chain(self.default.iter_mut().filter_map(|d| d.remote.as_mut()))
+            .chain(
+                self.superproject
+                    .iter_mut()
+                    .filter_map(|s| s.remote.as_mut()),
+            )
+            .chain(
+                self.submanifests
+                    .iter_mut()
+                    .filter_map(|s| s.remote.as_mut()),
+            );
+        for name in references.filter(|name| *name == from) {
+            *name = to.to_string();
+        }
+        Ok(())
+    }
+}
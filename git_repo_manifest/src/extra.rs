@@ -0,0 +1,346 @@
+use crate::document::{Document, DocumentError, ElementId};
+use crate::format::format_manifest;
+use crate::{
+    de::DeError, se, Annotation, ContactInfo, CopyFile, DefaultTag, ExtendProject, Include,
+    LinkFile, Manifest, ManifestServer, Notice, Project, Remote, RemoveProject, RepoHooks,
+    Submanifest, Superproject,
+};
+use derive_getters::Getters;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnknownKind {
+    Attribute,
+    Element,
+}
+
+/// An attribute or element which git_repo_manifest does not model.
+#[derive(Debug, Clone, Getters)]
+pub struct Unknown {
+    kind: UnknownKind,
+    name: String,
+    /// The attribute's value, or the element's source.
+    value: String,
+    /// The name of the element it appeared on or in.
+    parent: String,
+    path: PathBuf,
+    line: usize,
+    column: usize,
+}
+
+/// Compares everything but the location, so that an element repeated in
+/// another file is still equal.
+impl PartialEq for Unknown {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.name == other.name && self.value == other.value
+    }
+}
+
+impl fmt::Display for Unknown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: ", self.path.display(), self.line, self.column)?;
+        match self.kind {
+            UnknownKind::Attribute => {
+                write!(f, "unknown attribute `{}` of <{}>", self.name, self.parent)
+            }
+            UnknownKind::Element => {
+                write!(f, "unknown element <{}> in <{}>", self.name, self.parent)
+            }
+        }
+    }
+}
+
+/// The unknown attributes and child elements of an element, kept by
+/// `parse_str` so that `Manifest::to_xml` can write them back out.
+#[derive(Clone, Default, PartialEq)]
+pub struct Extra(Vec<Unknown>);
+
+impl fmt::Debug for Extra {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.0).finish()
+    }
+}
+
+impl Extra {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Unknown> {
+        self.0.iter()
+    }
+
+    /// The value of the unknown attribute `name`.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes()
+            .find(|(attribute, _)| *attribute == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The unknown attributes as name and value pairs, in source order.
+    pub fn attributes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .filter(|u| u.kind == UnknownKind::Attribute)
+            .map(|u| (u.name.as_str(), u.value.as_str()))
+    }
+
+    pub(crate) fn extend(&mut self, other: Extra) {
+        self.0.extend(other.0);
+    }
+
+    /// The source of each unknown child element, in source order.
+    pub fn elements(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter(|u| u.kind == UnknownKind::Element)
+            .map(|u| u.value.as_str())
+    }
+}
+
+/// Either a single optional child or a list of them.
+trait Children<T> {
+    fn get_child(&self, index: usize) -> Option<&T>;
+    fn get_child_mut(&mut self, index: usize) -> Option<&mut T>;
+}
+
+impl<T> Children<T> for Vec<T> {
+    fn get_child(&self, index: usize) -> Option<&T> {
+        self.get(index)
+    }
+    fn get_child_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.get_mut(index)
+    }
+}
+
+impl<T> Children<T> for Option<T> {
+    fn get_child(&self, index: usize) -> Option<&T> {
+        self.as_ref().filter(|_| index == 0)
+    }
+    fn get_child_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.as_mut().filter(|_| index == 0)
+    }
+}
+
+/// A modelled element, along with the attribute and child element names its
+/// serde attributes accept.
+trait Element {
+    fn attribute_names(&self) -> &'static [&'static str];
+    fn child_names(&self) -> &'static [&'static str];
+    fn extra_ref(&self) -> &Extra;
+    fn extra_mut(&mut self) -> &mut Extra;
+    /// Child `index` among those named `name`.
+    fn child(&self, name: &str, index: usize) -> Option<&dyn Element>;
+    fn child_mut(&mut self, name: &str, index: usize) -> Option<&mut dyn Element>;
+}
+
+macro_rules! elements {
+    ($($ty:ty = $tag:literal { [$($attribute:literal),*] $($child:literal => $field:ident,)* })*) => {
+        /// The attributes of the element `tag`, in the order repo documents them.
+        pub(crate) fn attribute_names(tag: &str) -> Option<&'static [&'static str]> {
+            match tag {
+                $($tag => Some(&[$($attribute),*]),)*
+                _ => None,
+            }
+        }
+    $(
+        impl Element for $ty {
+            fn attribute_names(&self) -> &'static [&'static str] {
+                &[$($attribute),*]
+            }
+            fn child_names(&self) -> &'static [&'static str] {
+                &[$($child),*]
+            }
+            fn extra_ref(&self) -> &Extra {
+                &self.extra
+            }
+            fn extra_mut(&mut self) -> &mut Extra {
+                &mut self.extra
+            }
+            #[allow(unused_variables)]
+            fn child(&self, name: &str, index: usize) -> Option<&dyn Element> {
+                match name {
+                    $($child => self.$field.get_child(index).map(|c| c as &dyn Element),)*
+                    _ => None,
+                }
+            }
+            #[allow(unused_variables)]
+            fn child_mut(&mut self, name: &str, index: usize) -> Option<&mut dyn Element> {
+                match name {
+                    $($child => self.$field.get_child_mut(index).map(|c| c as &mut dyn Element),)*
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+// Must agree with the serde attributes in lib.rs.
+elements! {
+    Manifest = "manifest" { []
+        "notice" => notice,
+        "manifest-server" => manifest_server,
+        "remote" => remotes,
+        "default" => default,
+        "remove-project" => remove_projects,
+        "project" => projects,
+        "extend-project" => extend_projects,
+        "repo-hooks" => repo_hooks,
+        "superproject" => superproject,
+        "contactinfo" => contactinfo,
+        "submanifest" => submanifests,
+        "include" => includes,
+    }
+    Notice = "notice" { [] }
+    Remote = "remote" { ["name", "alias", "pushurl", "fetch", "review", "revision", "type", "override"]
+        "annotation" => annotations,
+    }
+    Annotation = "annotation" { ["name", "value", "keep"] }
+    DefaultTag = "default" { ["remote", "revision", "dest-branch", "upstream", "sync-j", "sync-c", "sync-s", "sync-tags"] }
+    ManifestServer = "manifest-server" { ["url"] }
+    RemoveProject = "remove-project" { ["name", "path", "optional", "base-rev"] }
+    ExtendProject = "extend-project" { ["name", "path", "dest-path", "groups", "revision", "remote", "dest-branch", "upstream", "base-rev"] }
+    Project = "project" { ["name", "path", "remote", "revision", "dest-branch", "groups", "rebase", "sync-c", "sync-s",
+               "sync-tags", "upstream", "clone-depth", "force-path"]
+        "linkfile" => linkfiles,
+        "copyfile" => copyfiles,
+        "annotation" => annotations,
+    }
+    LinkFile = "linkfile" { ["src", "dest"] }
+    CopyFile = "copyfile" { ["src", "dest"] }
+    RepoHooks = "repo-hooks" { ["in-project", "enabled-list"] }
+    Include = "include" { ["name"] }
+    Superproject = "superproject" { ["name", "remote", "revision"] }
+    ContactInfo = "contactinfo" { ["bugurl"] }
+    Submanifest = "submanifest" { ["name", "remote", "project", "revision", "manifest-name", "path", "groups", "default-groups"] }
+}
+
+/// Numbers each child of `id` among its siblings of the same name.
+fn indexed_children<'a>(
+    doc: &'a Document,
+    id: ElementId,
+) -> impl Iterator<Item = (&'a str, usize, ElementId)> + 'a {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    doc.children(id).iter().map(move |&child| {
+        let name = doc.name(child);
+        let count = counts.entry(name).or_insert(0);
+        *count += 1;
+        (name, *count - 1, child)
+    })
+}
+
+fn attach(element: &mut dyn Element, doc: &Document, id: ElementId, path: &Path) {
+    let text = doc.as_str();
+    let unknown = |kind, name: &str, value: String, offset: usize| {
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        Unknown {
+            kind,
+            name: name.to_string(),
+            value,
+            parent: doc.name(id).to_string(),
+            path: path.to_path_buf(),
+            line: text[..offset].matches('\n').count() + 1,
+            column: text[line_start..offset].chars().count() + 1,
+        }
+    };
+    for (name, value, offset) in doc.attributes_with_offsets(id) {
+        if !element.attribute_names().contains(&name.as_str()) && !name.starts_with("xmlns") {
+            let unknown = unknown(UnknownKind::Attribute, &name, value, offset);
+            element.extra_mut().0.push(unknown);
+        }
+    }
+    for (name, index, child) in indexed_children(doc, id) {
+        if element.child_names().contains(&name) {
+            if let Some(child_element) = element.child_mut(name, index) {
+                attach(child_element, doc, child, path);
+            }
+        } else {
+            let source = doc.source(child).to_string();
+            // Point at the name, past the `<`.
+            let unknown = unknown(UnknownKind::Element, name, source, doc.offset(child) + 1);
+            element.extra_mut().0.push(unknown);
+        }
+    }
+}
+
+/// Records the unknown attributes and elements of `source` in the `extra` of
+/// each element of `manifest`, which was deserialized from it.
+pub(crate) fn attach_extras(manifest: &mut Manifest, source: &str, path: &Path) {
+    // `source` deserialized successfully, so it is well formed.
+    if let Ok(doc) = Document::parse(source.to_string()) {
+        attach(manifest, &doc, doc.root(), path);
+    }
+}
+
+fn collect_unknowns<'a>(element: &'a dyn Element, unknowns: &mut Vec<&'a Unknown>) {
+    unknowns.extend(element.extra_ref().iter());
+    for name in element.child_names() {
+        let mut index = 0;
+        while let Some(child) = element.child(name, index) {
+            collect_unknowns(child, unknowns);
+            index += 1;
+        }
+    }
+}
+
+fn collect_extras<'a>(
+    element: &'a dyn Element,
+    doc: &Document,
+    id: ElementId,
+    extras: &mut Vec<(ElementId, &'a Extra)>,
+) {
+    if !element.extra_ref().is_empty() {
+        extras.push((id, element.extra_ref()));
+    }
+    for (name, index, child) in indexed_children(doc, id) {
+        if let Some(child_element) = element.child(name, index) {
+            collect_extras(child_element, doc, child, extras);
+        }
+    }
+}
+
+impl Manifest {
+    /// Every unknown attribute and element kept while parsing, ordered by
+    /// location. Strict mode rejects a manifest for which this is not empty.
+    pub fn unknowns(&self) -> Vec<&Unknown> {
+        let mut unknowns = Vec::new();
+        collect_unknowns(self, &mut unknowns);
+        unknowns.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+        unknowns
+    }
+
+    /// Serializes the manifest in the canonical form of `format_manifest`,
+    /// along with the unknown attributes and elements kept in each element's
+    /// `extra`.
+    pub fn to_xml(&self) -> Result<String, DeError> {
+        let mut output = Vec::new();
+        let writer = quick_xml::Writer::new(&mut output);
+        let mut ser = se::Serializer::with_root(writer, None);
+        self.serialize(&mut ser)?;
+        let text = String::from_utf8_lossy(&output).into_owned();
+
+        let mut doc = Document::parse(text).map_err(document_error)?;
+        let mut extras = Vec::new();
+        collect_extras(self, &doc, doc.root(), &mut extras);
+        // Later elements first, so that the ids of earlier ones stay valid.
+        for (id, extra) in extras.into_iter().rev() {
+            for (name, value) in extra.attributes() {
+                doc.set_attribute(id, name, value);
+            }
+            for element in extra.elements() {
+                doc.append_child(id, element).map_err(document_error)?;
+            }
+        }
+        format_manifest(&doc.to_string(), false).map_err(document_error)
+    }
+}
+
+fn document_error(err: DocumentError) -> DeError {
+    match err {
+        DocumentError::Xml(err) => DeError::Xml(err),
+        err => DeError::Custom(err.to_string()),
+    }
+}
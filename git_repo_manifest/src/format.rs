@@ -0,0 +1,265 @@
+use crate::document::DocumentError;
+use crate::extra::attribute_names;
+use quick_xml::escape::{escape, unescape};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fmt::Write;
+
+const INDENT: &str = "  ";
+
+#[derive(Debug)]
+enum Node {
+    Element {
+        name: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<Node>,
+    },
+    Comment(String),
+    /// Text other than whitespace, kept as written.
+    Text(String),
+    /// One or more blank lines between siblings.
+    Blank,
+    /// The XML declaration and anything else kept verbatim.
+    Raw(String),
+}
+
+impl Node {
+    fn is_project(&self) -> bool {
+        matches!(self, Node::Element { name, .. } if name == "project")
+    }
+
+    fn attribute(&self, attribute: &str) -> Option<&str> {
+        match self {
+            Node::Element { attributes, .. } => attributes
+                .iter()
+                .find(|(name, _)| name == attribute)
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+fn element(start: &BytesStart, reader: &Reader<&[u8]>) -> Result<Node, DocumentError> {
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute?;
+        let value = unescape(&attribute.value).map_err(quick_xml::Error::EscapeError)?;
+        attributes.push((
+            reader.decode(attribute.key)?.to_string(),
+            reader.decode(&value)?.to_string(),
+        ));
+    }
+    Ok(Node::Element {
+        name: reader.decode(start.name())?.to_string(),
+        attributes,
+        children: Vec::new(),
+    })
+}
+
+fn parse(source: &str) -> Result<Vec<Node>, DocumentError> {
+    let mut reader = Reader::from_str(source);
+    reader.check_end_names(true);
+    // The top level, followed by each open element.
+    let mut stack: Vec<Vec<Node>> = vec![Vec::new()];
+    let mut open: Vec<Node> = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        let event = reader.read_event(&mut buf)?;
+        let raw = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        let node = match event {
+            Event::Eof => break,
+            Event::Start(start) => {
+                open.push(element(&start, &reader)?);
+                stack.push(Vec::new());
+                None
+            }
+            Event::End(_) => {
+                let children = stack.pop().unwrap_or_default();
+                match open.pop() {
+                    Some(Node::Element {
+                        name, attributes, ..
+                    }) => Some(Node::Element {
+                        name,
+                        attributes,
+                        children,
+                    }),
+                    _ => None,
+                }
+            }
+            Event::Empty(start) => Some(element(&start, &reader)?),
+            Event::Text(text) if text.iter().all(u8::is_ascii_whitespace) => {
+                let lines = text.iter().filter(|&&c| c == b'\n').count();
+                Some(Node::Blank).filter(|_| lines > 1)
+            }
+            Event::Text(text) => Some(Node::Text(raw(&text))),
+            Event::Comment(comment) => Some(Node::Comment(raw(&comment))),
+            Event::CData(cdata) => Some(Node::Raw(format!("<![CDATA[{}]]>", raw(&cdata)))),
+            Event::Decl(decl) => Some(Node::Raw(format!("<?{}?>", raw(&decl)))),
+            Event::PI(pi) => Some(Node::Raw(format!("<?{}?>", raw(&pi)))),
+            Event::DocType(doctype) => Some(Node::Raw(format!("<!DOCTYPE{}>", raw(&doctype)))),
+        };
+        if let (Some(node), Some(siblings)) = (node, stack.last_mut()) {
+            siblings.push(node);
+        }
+        buf.clear();
+    }
+    let nodes = stack.pop().unwrap_or_default();
+    if nodes
+        .iter()
+        .any(|node| matches!(node, Node::Element { .. }))
+    {
+        Ok(nodes)
+    } else {
+        Err(DocumentError::NoRoot)
+    }
+}
+
+/// Sorts each run of adjacent `<project>`s by path, moving the comments
+/// directly above a project along with it. Blank lines and other elements
+/// end a run, so sections of a manifest stay apart.
+fn sort_projects(children: Vec<Node>) -> Vec<Node> {
+    fn flush(run: &mut Vec<Vec<Node>>, sorted: &mut Vec<Node>) {
+        run.sort_by(|a, b| {
+            let key = |group: &[Node]| {
+                let project = group.last().unwrap();
+                project
+                    .attribute("path")
+                    .or_else(|| project.attribute("name"))
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            key(a).cmp(&key(b))
+        });
+        sorted.extend(run.drain(..).flatten());
+    }
+    let mut sorted = Vec::new();
+    let mut run: Vec<Vec<Node>> = Vec::new();
+    let mut comments = Vec::new();
+    for child in children {
+        match child {
+            Node::Comment(_) => comments.push(child),
+            child if child.is_project() => {
+                comments.push(child);
+                run.push(std::mem::take(&mut comments));
+            }
+            child => {
+                flush(&mut run, &mut sorted);
+                sorted.append(&mut comments);
+                sorted.push(child);
+            }
+        }
+    }
+    flush(&mut run, &mut sorted);
+    sorted.append(&mut comments);
+    sorted
+}
+
+fn write_attributes(out: &mut String, element: &str, attributes: &[(String, String)]) {
+    let order = attribute_names(element).unwrap_or_default();
+    let mut attributes: Vec<_> = attributes.iter().collect();
+    // Unknown attributes follow the known ones in their original order.
+    attributes.sort_by_key(|(name, _)| {
+        order
+            .iter()
+            .position(|known| known == name)
+            .unwrap_or(order.len())
+    });
+    for (name, value) in attributes {
+        let value = if element == "remote" && name == "type" {
+            value.to_lowercase()
+        } else {
+            value.to_string()
+        };
+        let value = escape(value.as_bytes());
+        let _ = write!(out, " {}=\"{}\"", name, String::from_utf8_lossy(&value));
+    }
+}
+
+fn write_nodes(out: &mut String, nodes: &[Node], depth: usize) {
+    let indent = INDENT.repeat(depth);
+    // Blank lines are collapsed and dropped at either end.
+    let last = nodes.iter().rposition(|node| !matches!(node, Node::Blank));
+    let mut blank = false;
+    let mut written = false;
+    for (i, node) in nodes.iter().enumerate() {
+        if let Node::Blank = node {
+            blank = written && Some(i) < last;
+            continue;
+        }
+        written = true;
+        if std::mem::take(&mut blank) {
+            out.push('\n');
+        }
+        match node {
+            Node::Element {
+                name,
+                attributes,
+                children,
+            } => {
+                let _ = write!(out, "{}<{}", indent, name);
+                write_attributes(out, name, attributes);
+                let text_only = children.iter().all(|c| matches!(c, Node::Text(_)));
+                if children.is_empty() {
+                    out.push_str("/>\n");
+                } else if text_only {
+                    out.push('>');
+                    for child in children {
+                        if let Node::Text(text) = child {
+                            out.push_str(text);
+                        }
+                    }
+                    let _ = writeln!(out, "</{}>", name);
+                } else {
+                    out.push_str(">\n");
+                    write_nodes(out, children, depth + 1);
+                    let _ = writeln!(out, "{}</{}>", indent, name);
+                }
+            }
+            Node::Comment(comment) => {
+                let _ = writeln!(out, "{}<!--{}-->", indent, comment);
+            }
+            Node::Text(text) => {
+                let _ = writeln!(out, "{}{}", indent, text.trim());
+            }
+            Node::Raw(raw) => {
+                let _ = writeln!(out, "{}{}", indent, raw);
+            }
+            Node::Blank => (),
+        }
+    }
+}
+
+fn sort_all(nodes: Vec<Node>) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .map(|node| match node {
+            Node::Element {
+                name,
+                attributes,
+                children,
+            } if name == "manifest" => Node::Element {
+                name,
+                attributes,
+                children: sort_projects(children),
+            },
+            node => node,
+        })
+        .collect()
+}
+
+/// Rewrites the manifest `source` in canonical form: one element per line
+/// indented by two spaces, attributes in the order repo documents them with
+/// unknown ones last, double quoted values, lowercase remote `type`s, and at
+/// most one blank line between elements.
+///
+/// Comments, text and unknown elements are kept. With `sort_projects`, runs
+/// of adjacent `<project>`s are sorted by path.
+pub fn format_manifest(source: &str, sort_projects: bool) -> Result<String, DocumentError> {
+    let mut nodes = parse(source)?;
+    if sort_projects {
+        nodes = sort_all(nodes);
+    }
+    let mut out = String::new();
+    write_nodes(&mut out, &nodes, 0);
+    Ok(out)
+}
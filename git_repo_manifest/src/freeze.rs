@@ -0,0 +1,58 @@
+use crate::{git, Manifest};
+use quick_error::quick_error;
+use std::io;
+use std::path::{Path, PathBuf};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum FreezeError {
+        Io(path: PathBuf, err: io::Error) {
+            display("{}: {}", path.display(), err)
+            source(err)
+        }
+        UnbornHead(path: PathBuf) {
+            display("{}: HEAD does not point to a commit", path.display())
+        }
+    }
+}
+
+impl Manifest {
+    /// Pins every project to the commit checked out beneath `workspace`, like
+    /// `repo manifest -r`.
+    ///
+    /// The branch a project followed is kept as its `upstream` and
+    /// `dest-branch`, unless those are already set, so that `repo upload`
+    /// still knows where to send changes.
+    pub fn freeze(&mut self, workspace: &Path) -> Result<(), FreezeError> {
+        let remotes = &self.remotes;
+        let default = self.default.as_ref();
+        for project in &mut self.projects {
+            let checkout = workspace.join(project.relpath());
+            let head = git::head_revision(&checkout)
+                .map_err(|err| FreezeError::Io(checkout.clone(), err))?
+                .ok_or_else(|| FreezeError::UnbornHead(checkout.clone()))?;
+
+            let remote = project
+                .remote
+                .as_ref()
+                .or_else(|| default?.remote.as_ref())
+                .and_then(|name| remotes.iter().find(|remote| remote.name == *name));
+            let branch = project
+                .revision
+                .clone()
+                .or_else(|| remote?.revision.clone())
+                .or_else(|| default?.revision.clone())
+                .filter(|revision| !git::is_object_id(revision));
+            if let Some(branch) = branch {
+                if project.upstream.is_none() {
+                    project.upstream = Some(branch.clone());
+                }
+                if project.dest_branch.is_none() {
+                    project.dest_branch = Some(branch);
+                }
+            }
+            project.revision = Some(head);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Looks up `key` in the `[section "subsection"]` of a git config file.
+///
+/// Only the subset of the format written by git itself is understood, which
+/// is enough to read the remotes of a repository created by repo.
+pub fn config_value(
+    config: &Path,
+    section: &str,
+    subsection: Option<&str>,
+    key: &str,
+) -> io::Result<Option<String>> {
+    let contents = fs::read_to_string(config)?;
+    let header = match subsection {
+        Some(subsection) => format!("[{} \"{}\"]", section, subsection),
+        None => format!("[{}]", section),
+    };
+    let mut in_section = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_section = line.eq_ignore_ascii_case(&header);
+        } else if in_section {
+            if let Some((name, value)) = line.split_once('=') {
+                if name.trim().eq_ignore_ascii_case(key) {
+                    return Ok(Some(value.trim().trim_matches('"').to_string()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The URL of the `origin` remote of the git directory `git_dir`, such as
+/// `.repo/manifests.git`.
+pub fn origin_url(git_dir: &Path) -> io::Result<Option<String>> {
+    config_value(&git_dir.join("config"), "remote", Some("origin"), "url")
+}
+
+/// The git directory of the checkout at `work_tree`, following a `.git` file
+/// containing `gitdir: <path>` as written by `git worktree` and newer repo.
+pub fn git_dir(work_tree: &Path) -> io::Result<PathBuf> {
+    let dot_git = work_tree.join(".git");
+    if dot_git.is_file() {
+        let contents = fs::read_to_string(&dot_git)?;
+        match contents.trim().strip_prefix("gitdir:") {
+            Some(path) => Ok(work_tree.join(path.trim())),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not contain a gitdir", dot_git.display()),
+            )),
+        }
+    } else {
+        Ok(dot_git)
+    }
+}
+
+/// Whether `value` is a full SHA-1 or SHA-256 object name.
+pub(crate) fn is_object_id(value: &str) -> bool {
+    (value.len() == 40 || value.len() == 64) && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// How many symbolic refs are followed before giving up, as in git.
+const SYMREF_MAXDEPTH: usize = 5;
+
+/// Looks up `name`, such as `refs/heads/master`, as a loose ref and then in
+/// `packed-refs`.
+fn read_ref(git_dir: &Path, name: &str, depth: usize) -> io::Result<Option<String>> {
+    // Linked worktrees keep their refs in the common directory.
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common_dir) => git_dir.join(common_dir.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    };
+    for dir in [git_dir, common_dir.as_path()].iter() {
+        match fs::read_to_string(dir.join(name)) {
+            Ok(contents) => return resolve_ref(git_dir, contents.trim(), depth),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+    }
+    let packed = match fs::read_to_string(common_dir.join("packed-refs")) {
+        Ok(packed) => packed,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok(packed
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| line.split_once(' '))
+        .find(|(_, ref_name)| *ref_name == name)
+        .map(|(id, _)| id.to_string()))
+}
+
+fn resolve_ref(git_dir: &Path, contents: &str, depth: usize) -> io::Result<Option<String>> {
+    match contents.strip_prefix("ref:") {
+        Some(name) if depth >= SYMREF_MAXDEPTH => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("symbolic ref {} is nested too deeply", name.trim()),
+        )),
+        Some(name) => read_ref(git_dir, name.trim(), depth + 1),
+        None if is_object_id(contents) => Ok(Some(contents.to_string())),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected ref contents {:?}", contents),
+        )),
+    }
+}
+
+/// The commit checked out at `work_tree`, read from the repository files
+/// without running git. `None` means HEAD is an unborn branch.
+pub fn head_revision(work_tree: &Path) -> io::Result<Option<String>> {
+    let git_dir = git_dir(work_tree)?;
+    let head = fs::read_to_string(git_dir.join("HEAD"))?;
+    resolve_ref(&git_dir, head.trim(), 0)
+}
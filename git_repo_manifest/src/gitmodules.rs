@@ -0,0 +1,373 @@
+use crate::url::{has_scheme, is_absolute, split_authority};
+use crate::{
+    DefaultTagBuilder, Manifest, Project, ProjectBuilder, Remote, RemoteBuilder, Revision,
+};
+use derive_getters::Getters;
+use derive_new::new;
+use quick_error::quick_error;
+use std::fmt::Write;
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum GitmodulesError {
+        UnknownRemote(project: String) {
+            display("project {} has no known remote", project)
+        }
+        RelativeUrl(project: String, url: String) {
+            display("project {} has the relative URL {}, which needs the manifest URL to resolve", project, url)
+        }
+        MissingKey(submodule: String, key: String) {
+            display("submodule {} has no {}", submodule, key)
+        }
+        Syntax(line: usize) {
+            display(".gitmodules:{}: expected a section header or `key = value`", line)
+        }
+    }
+}
+
+/// A `.gitmodules` entry, along with the commit its gitlink points to.
+#[derive(Debug, PartialEq, Clone, Getters, new)]
+pub struct Submodule {
+    name: String,
+    path: String,
+    url: String,
+    branch: Option<String>,
+    /// The commit id of the gitlink, which `.gitmodules` itself does not hold.
+    revision: Option<String>,
+}
+
+/// The branch name of `revision`, if it names a branch.
+fn branch_name(revision: Option<Revision>) -> Option<String> {
+    match revision? {
+        Revision::Branch(name) => Some(name),
+        _ => None,
+    }
+}
+
+impl Manifest {
+    /// One submodule per project, named and checked out at the project's
+    /// path, cloned from its absolute URL and following its `upstream`,
+    /// `dest-branch` or `revision`, whichever first names a branch. A project
+    /// whose `revision` is a commit id is pinned to it. Relative fetch URLs
+    /// are resolved against `manifest_url`, and are an error without it.
+    ///
+    /// Call `set_defaults` first, so that every project has its effective
+    /// remote and revision.
+    pub fn submodules(
+        &self,
+        manifest_url: Option<&str>,
+    ) -> Result<Vec<Submodule>, GitmodulesError> {
+        let mut submodules = Vec::new();
+        for project in &self.projects {
+            let url = self
+                .clone_url(project, manifest_url)
+                .ok_or_else(|| GitmodulesError::UnknownRemote(project.name.clone()))?;
+            if !is_absolute(&url) {
+                return Err(GitmodulesError::RelativeUrl(project.name.clone(), url));
+            }
+            let branch = branch_name(project.typed_upstream())
+                .or_else(|| branch_name(project.typed_dest_branch()))
+                .or_else(|| branch_name(project.typed_revision()));
+            let revision = match project.typed_revision() {
+                Some(Revision::CommitId(id)) => Some(id),
+                _ => None,
+            };
+            let path = project.relpath().to_string();
+            submodules.push(Submodule::new(path.clone(), path, url, branch, revision));
+        }
+        Ok(submodules)
+    }
+
+    /// A manifest cloning every submodule to its path. Each distinct fetch
+    /// URL becomes a remote named after its host, the first one being the
+    /// `<default>` remote. Pinned submodules keep their branch as `upstream`
+    /// and `dest-branch`.
+    pub fn from_submodules(submodules: &[Submodule]) -> Manifest {
+        let mut manifest = <Manifest as Default>::default();
+        for submodule in submodules {
+            let (fetch, name) = split_url(&submodule.url);
+            let remote = match manifest.remotes.iter().find(|r| r.fetch == fetch) {
+                Some(remote) => remote.name.clone(),
+                None => {
+                    let remote = remote_name(&manifest.remotes, &fetch);
+                    manifest.remotes.push(
+                        RemoteBuilder::default()
+                            .name(remote.as_str())
+                            .fetch(fetch)
+                            .build()
+                            .unwrap(),
+                    );
+                    remote
+                }
+            };
+            let mut project = ProjectBuilder::default().name(name.as_str());
+            if submodule.path != name {
+                project = project.path(submodule.path.as_str());
+            }
+            if remote != manifest.remotes[0].name {
+                project = project.remote(remote.as_str());
+            }
+            project = match (&submodule.revision, &submodule.branch) {
+                (Some(revision), Some(branch)) => project
+                    .revision(revision.as_str())
+                    .upstream(branch.as_str())
+                    .dest_branch(branch.as_str()),
+                (Some(revision), None) => project.revision(revision.as_str()),
+                (None, Some(branch)) => project.revision(branch.as_str()),
+                (None, None) => project,
+            };
+            let project: Project = project.build().unwrap();
+            manifest.projects.push(project);
+        }
+        if let Some(remote) = manifest.remotes.first() {
+            manifest.default = Some(
+                DefaultTagBuilder::default()
+                    .remote(remote.name.as_str())
+                    .build()
+                    .unwrap(),
+            );
+        }
+        manifest
+    }
+}
+
+/// Splits a clone URL into a remote `fetch` URL and a project name, at the
+/// start of the path for URLs with a scheme and at the last `/` otherwise.
+fn split_url(url: &str) -> (String, String) {
+    let (fetch, name) = if has_scheme(url) {
+        split_authority(url)
+    } else {
+        match url.rfind('/') {
+            Some(slash) => url.split_at(slash),
+            None => match url.find(':') {
+                // An scp-like URL with the repository in the home directory.
+                Some(colon) => url.split_at(colon + 1),
+                None => (".", url),
+            },
+        }
+    };
+    let fetch = match fetch.strip_suffix(':') {
+        Some(_) => format!("{}.", fetch),
+        None => fetch.to_string(),
+    };
+    (fetch, name.trim_start_matches('/').to_string())
+}
+
+/// The host of `fetch`, or `origin` for a relative URL, made unique among
+/// `remotes` with a numeric suffix.
+fn remote_name(remotes: &[Remote], fetch: &str) -> String {
+    let host = match fetch.find("://") {
+        Some(scheme_end) => &fetch[scheme_end + 3..],
+        None => fetch.split(':').next().unwrap_or_default(),
+    };
+    let host = host.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    let base = if host.is_empty() || host.starts_with('.') {
+        "origin"
+    } else {
+        host
+    };
+    let taken = |name: &str| remotes.iter().any(|r| r.name == name);
+    let mut name = base.to_string();
+    let mut suffix = 1;
+    while taken(&name) {
+        suffix += 1;
+        name = format!("{}-{}", base, suffix);
+    }
+    name
+}
+
+/// `value` in double quotes, escaped the way git-config writes values.
+fn quote_value(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The `.gitmodules` file describing `submodules`. Values are quoted, so
+/// paths and URLs may hold any character. Section names may not hold a
+/// newline, which git-config has no escape for.
+pub fn write_gitmodules(submodules: &[Submodule]) -> String {
+    let mut out = String::new();
+    for submodule in submodules {
+        let name = submodule.name.replace('\\', "\\\\").replace('"', "\\\"");
+        let _ = writeln!(out, "[submodule \"{}\"]", name);
+        let _ = writeln!(out, "\tpath = {}", quote_value(&submodule.path));
+        let _ = writeln!(out, "\turl = {}", quote_value(&submodule.url));
+        if let Some(branch) = &submodule.branch {
+            let _ = writeln!(out, "\tbranch = {}", quote_value(branch));
+        }
+    }
+    out
+}
+
+/// A shell script which, run at the top of the superproject next to the
+/// `.gitmodules` from `write_gitmodules`, adds a gitlink for every pinned
+/// submodule, ready to be committed.
+pub fn pin_script(submodules: &[Submodule]) -> String {
+    let mut out = String::from("#!/bin/sh\nset -e\n");
+    for submodule in submodules {
+        match &submodule.revision {
+            Some(revision) => {
+                let _ = writeln!(
+                    out,
+                    "git update-index --add --cacheinfo 160000,{},'{}'",
+                    revision,
+                    submodule.path.replace('\'', "'\\''")
+                );
+            }
+            None => {
+                // Debug formatting keeps a newline from ending the comment.
+                let _ = writeln!(out, "# {:?} is not pinned to a commit", submodule.path);
+            }
+        }
+    }
+    out
+}
+
+/// Reads the `[submodule]` sections of a `.gitmodules` file, ignoring other
+/// sections and keys. Revisions are left unset, see `apply_gitlinks`.
+pub fn parse_gitmodules(text: &str) -> Result<Vec<Submodule>, GitmodulesError> {
+    struct Section {
+        name: String,
+        path: Option<String>,
+        url: Option<String>,
+        branch: Option<String>,
+    }
+    let mut sections: Vec<Section> = Vec::new();
+    let mut in_submodule = false;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let (header, comment) = header
+                .rsplit_once(']')
+                .ok_or(GitmodulesError::Syntax(i + 1))?;
+            let comment = comment.trim_start();
+            if !(comment.is_empty() || comment.starts_with(';') || comment.starts_with('#')) {
+                return Err(GitmodulesError::Syntax(i + 1));
+            }
+            let name = header
+                .strip_prefix("submodule")
+                .map(str::trim)
+                .and_then(|name| name.strip_prefix('"')?.strip_suffix('"'));
+            in_submodule = name.is_some();
+            if let Some(name) = name {
+                sections.push(Section {
+                    name: unescape_name(name),
+                    path: None,
+                    url: None,
+                    branch: None,
+                });
+            }
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or(GitmodulesError::Syntax(i + 1))?;
+        let value = Some(parse_value(value).ok_or(GitmodulesError::Syntax(i + 1))?);
+        let section = match sections.last_mut() {
+            Some(section) if in_submodule => section,
+            _ => continue,
+        };
+        match key.trim().to_lowercase().as_str() {
+            "path" => section.path = value,
+            "url" => section.url = value,
+            "branch" => section.branch = value,
+            _ => (),
+        }
+    }
+    sections
+        .into_iter()
+        .map(|section| {
+            let missing = |key: &str| GitmodulesError::MissingKey(section.name.clone(), key.into());
+            let path = section.path.clone().ok_or_else(|| missing("path"))?;
+            let url = section.url.clone().ok_or_else(|| missing("url"))?;
+            Ok(Submodule::new(
+                section.name,
+                path,
+                url,
+                section.branch,
+                None,
+            ))
+        })
+        .collect()
+}
+
+/// A section name without the escapes of its quoted form, where a backslash
+/// quotes the character after it.
+fn unescape_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A value as git-config reads it: double quotes are removed, `\\`, `\"`,
+/// `\n`, `\t` and `\b` are unescaped, `;` and `#` outside quotes start a
+/// comment, and unquoted whitespace is trimmed at either end. `None` for an
+/// unknown escape or an unterminated quote.
+fn parse_value(value: &str) -> Option<String> {
+    let mut out = String::new();
+    // The length of `out` up to its last quoted or escaped character, which
+    // trimming stops at.
+    let mut kept = 0;
+    let mut quoted = false;
+    let mut chars = value.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' | '#' if !quoted => break,
+            '\\' => {
+                out.push(match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'b' => '\u{8}',
+                    c @ ('\\' | '"') => c,
+                    _ => return None,
+                });
+            }
+            c => out.push(c),
+        }
+        if quoted || c == '\\' || c == '"' {
+            kept = out.len();
+        }
+    }
+    if quoted {
+        return None;
+    }
+    let end = kept.max(out.trim_end().len());
+    out.truncate(end);
+    Some(out)
+}
+
+/// Sets the revision of each submodule from the gitlinks listed by
+/// `git ls-tree -r HEAD`, lines such as `160000 commit <id>\t<path>`.
+pub fn apply_gitlinks(submodules: &mut [Submodule], ls_tree: &str) {
+    for line in ls_tree.lines() {
+        let (info, path) = match line.split_once('\t') {
+            Some(split) => split,
+            None => continue,
+        };
+        let fields: Vec<&str> = info.split_whitespace().collect();
+        if let ["160000", "commit", id] = fields[..] {
+            for submodule in submodules.iter_mut().filter(|s| s.path == path) {
+                submodule.revision = Some(id.to_string());
+            }
+        }
+    }
+}
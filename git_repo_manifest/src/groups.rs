@@ -0,0 +1,112 @@
+use crate::{Manifest, Project};
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+/// Splits a `groups` attribute or a filter on commas and whitespace.
+pub(crate) fn split_groups(groups: &str) -> impl Iterator<Item = &str> {
+    groups
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|group| !group.is_empty())
+}
+
+impl Project {
+    /// The groups the project belongs to, those listed in `groups` followed
+    /// by the implicit `all`, `name:<name>`, `path:<path>` and `default`,
+    /// which is left out when the project is in `notdefault`.
+    pub fn group_set(&self) -> Vec<String> {
+        let mut groups: Vec<String> = Vec::new();
+        let explicit = self.groups.as_deref().map(split_groups);
+        let implicit = vec![
+            "all".to_string(),
+            format!("name:{}", self.name),
+            format!("path:{}", self.relpath()),
+        ];
+        for group in explicit
+            .into_iter()
+            .flatten()
+            .map(str::to_string)
+            .chain(implicit)
+        {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        if !groups.iter().any(|group| group == "notdefault") {
+            groups.push("default".to_string());
+        }
+        groups
+    }
+
+    /// Whether `filter` selects the project.
+    pub fn in_groups(&self, filter: &GroupFilter) -> bool {
+        filter.matches(&self.group_set())
+    }
+}
+
+/// A repo `-g` style group filter such as `default,-notdefault,platform-linux`.
+///
+/// The entries are applied in order, a project is selected by the last entry
+/// naming one of its groups, with a leading `-` deselecting it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GroupFilter(Vec<String>);
+
+impl GroupFilter {
+    pub fn matches<S: AsRef<str>>(&self, groups: &[S]) -> bool {
+        let contains = |name: &str| groups.iter().any(|group| group.as_ref() == name);
+        let mut matched = false;
+        for entry in &self.0 {
+            match entry.strip_prefix('-') {
+                Some(excluded) if contains(excluded) => matched = false,
+                Some(_) => (),
+                None if contains(entry) => matched = true,
+                None => (),
+            }
+        }
+        matched
+    }
+}
+
+impl Default for GroupFilter {
+    /// Like repo, `default` along with the group of the current platform.
+    fn default() -> Self {
+        let platform = match std::env::consts::OS {
+            "macos" => "darwin",
+            os => os,
+        };
+        GroupFilter(vec![
+            "default".to_string(),
+            format!("platform-{}", platform),
+        ])
+    }
+}
+
+impl FromStr for GroupFilter {
+    type Err = Infallible;
+
+    /// An empty filter is the default one.
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let entries: Vec<String> = split_groups(filter).map(str::to_string).collect();
+        if entries.is_empty() {
+            Ok(GroupFilter::default())
+        } else {
+            Ok(GroupFilter(entries))
+        }
+    }
+}
+
+impl fmt::Display for GroupFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join(","))
+    }
+}
+
+impl Manifest {
+    /// The projects selected by `filter`.
+    pub fn projects_in_groups(&self, filter: &GroupFilter) -> Vec<&Project> {
+        self.projects
+            .iter()
+            .filter(|p| p.in_groups(filter))
+            .collect()
+    }
+}
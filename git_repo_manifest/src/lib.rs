@@ -1,74 +1,225 @@
+// derive(new) generates a positional constructor covering every field.
+#![allow(clippy::too_many_arguments)]
+
+use derive_builder::Builder;
 use derive_getters::Getters;
 use derive_new::new;
 use quick_error::quick_error;
 pub use quick_xml::de;
 pub use quick_xml::se;
-use serde::de::Deserializer;
-use serde::{Deserialize, Serialize};
+use serde::de::{Deserializer, Error as _};
+use serde::{Deserialize, Serialize, Serializer};
 use std::convert::TryFrom;
 use std::str::FromStr;
+
+mod diagnostic;
+mod diff;
+mod document;
+mod edit;
+mod extra;
+mod format;
+mod freeze;
+pub mod git;
+mod gitmodules;
+mod groups;
+mod loader;
+mod resolve;
+mod revision;
+mod split;
+mod submanifest;
 #[cfg(test)]
 mod test;
-
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+mod url;
+mod validate;
+
+pub use diagnostic::{parse_str, ParseError};
+pub use diff::Change;
+pub use document::{Document, DocumentError, ElementId};
+pub use edit::EditError;
+pub use extra::{Extra, Unknown, UnknownKind};
+pub use format::format_manifest;
+pub use freeze::FreezeError;
+pub use gitmodules::{
+    apply_gitlinks, parse_gitmodules, pin_script, write_gitmodules, GitmodulesError, Submodule,
+};
+pub use groups::GroupFilter;
+pub use loader::{load, load_with_local_manifests, parse_file, IncludeChain, LoadError};
+pub use resolve::ResolveError;
+pub use revision::Revision;
+pub use split::SplitBy;
+pub use submanifest::{load_submanifests, LoadedSubmanifest};
+pub use url::resolve_url;
+pub use validate::ValidationError;
+
+/// The JSON Schema of a `Manifest` serialized with serde_json, which also
+/// describes the YAML and TOML serializations.
+pub const JSON_SCHEMA: &str = include_str!("../manifest.schema.json");
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Default, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option), default)]
 #[serde(rename = "manifest")]
 pub struct Manifest {
     notice: Option<Notice>,
+
+    #[serde(rename = "manifest-server")]
     manifest_server: Option<ManifestServer>,
 
     #[serde(rename = "remote", default)]
+    #[builder(setter(each(name = "remote", into)))]
     remotes: Vec<Remote>,
 
+    #[builder(setter(name = "default_tag"))]
     default: Option<DefaultTag>,
 
     #[serde(rename = "remove-project", default)]
+    #[builder(setter(each(name = "remove_project", into)))]
     remove_projects: Vec<RemoveProject>,
 
     #[serde(rename = "project", default)]
+    #[builder(setter(each(name = "project", into)))]
     projects: Vec<Project>,
 
     #[serde(rename = "extend-project", default)]
+    #[builder(setter(each(name = "extend_project", into)))]
     extend_projects: Vec<ExtendProject>,
 
     #[serde(rename = "repo-hooks")]
     repo_hooks: Option<RepoHooks>,
 
+    superproject: Option<Superproject>,
+
+    contactinfo: Option<ContactInfo>,
+
+    #[serde(rename = "submanifest", default)]
+    #[builder(setter(each(name = "submanifest", into)))]
+    submanifests: Vec<Submanifest>,
+
     #[serde(rename = "include", default)]
+    #[builder(setter(each(name = "include", into)))]
     includes: Vec<Include>,
+
+    #[serde(skip)]
+    #[new(default)]
+    extra: Extra,
 }
 
 impl Manifest {
-    // FIXME add more defaults.
+    /// Fills in every project attribute which repo inherits from `<default>`
+    /// or the project's `<remote>`, after which each `Project` reports its
+    /// effective values.
+    ///
+    /// `revision` is taken from the project, then its remote, then the default.
+    /// `remote`, `dest-branch`, `upstream`, `sync-c`, `sync-s` and `sync-tags`
+    /// are taken from the project, then the default. The `<superproject>`
+    /// inherits `remote` and `revision` the same way.
     pub fn set_defaults(&mut self) {
-        if let Some(default) = &self.default {
-            if let Some(remote) = &default.remote {
-                for project in &mut self.projects {
-                    if project.remote == None {
-                        project.remote = Some(remote.to_string());
-                    }
-                }
+        let remotes = &self.remotes;
+        let default = self.default.as_ref();
+        fn inherit<T: Clone>(
+            value: &mut Option<T>,
+            default: Option<&DefaultTag>,
+            field: fn(&DefaultTag) -> &Option<T>,
+        ) {
+            if value.is_none() {
+                *value = default.and_then(|default| field(default).clone());
             }
         }
+        for project in &mut self.projects {
+            inherit(&mut project.remote, default, |d| &d.remote);
+            if project.revision.is_none() {
+                project.revision = project
+                    .remote
+                    .as_ref()
+                    .and_then(|name| remotes.iter().find(|remote| remote.name == *name))
+                    .and_then(|remote| remote.revision.clone());
+            }
+            inherit(&mut project.revision, default, |d| &d.revision);
+            inherit(&mut project.dest_branch, default, |d| &d.dest_branch);
+            inherit(&mut project.upstream, default, |d| &d.upstream);
+            inherit(&mut project.sync_c, default, |d| &d.sync_c);
+            inherit(&mut project.sync_s, default, |d| &d.sync_s);
+            inherit(&mut project.sync_tags, default, |d| &d.sync_tags);
+        }
+        if let Some(superproject) = &mut self.superproject {
+            inherit(&mut superproject.remote, default, |d| &d.remote);
+            if superproject.revision.is_none() {
+                superproject.revision = superproject
+                    .remote
+                    .as_ref()
+                    .and_then(|name| remotes.iter().find(|remote| remote.name == *name))
+                    .and_then(|remote| remote.revision.clone());
+            }
+            inherit(&mut superproject.revision, default, |d| &d.revision);
+        }
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+#[derive(Deserialize, Debug, PartialEq, Default, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option), default)]
 pub struct Notice {
     #[serde(rename = "$value", default)]
     notice: Option<String>,
+
+    #[serde(skip)]
+    #[new(default)]
+    extra: Extra,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+/// Written as a newtype, the only way to get the serializer to emit text
+/// content rather than a `$value` attribute.
+impl Serialize for Notice {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let notice = self.notice.as_deref().unwrap_or_default();
+        serializer.serialize_newtype_struct("notice", notice)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
 pub struct Remote {
     name: String,
+    #[builder(default)]
     alias: Option<String>,
+    #[builder(default)]
     pushurl: Option<String>,
     fetch: String,
+    #[builder(default)]
     review: Option<String>,
+    #[builder(default)]
     revision: Option<String>,
     // https://git-repo.info extensions
+    #[builder(default)]
     r#type: Option<ReviewProtocolType>,
+    #[serde(default, deserialize_with = "override_bool")]
+    #[builder(default)]
     r#override: Option<bool>,
+
+    #[serde(rename = "annotation", default)]
+    #[builder(default, setter(each(name = "annotation", into)))]
+    annotations: Vec<Annotation>,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
+}
+
+/// An `<annotation>` child of a project or remote, `keep` defaults to true
+/// and controls whether repo exports it to `repo forall`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
+pub struct Annotation {
+    name: String,
+    value: String,
+
+    #[serde(default, deserialize_with = "keep_bool")]
+    #[builder(default)]
+    keep: Option<bool>,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
 }
 
 quick_error! {
@@ -80,13 +231,31 @@ quick_error! {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Copy, Clone)]
+#[derive(Deserialize, Debug, PartialEq, Copy, Clone)]
 #[serde(try_from = "String")]
 pub enum ReviewProtocolType {
     AGit,
     Gerrit,
 }
 
+impl ReviewProtocolType {
+    /// The lowercase `type` attribute value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewProtocolType::AGit => "agit",
+            ReviewProtocolType::Gerrit => "gerrit",
+        }
+    }
+}
+
+/// Written as the attribute value rather than the variant name, which the
+/// serializer would turn into a child element.
+impl Serialize for ReviewProtocolType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 impl TryFrom<String> for ReviewProtocolType {
     type Error = ProtocolTypeError;
     fn try_from(it: String) -> Result<Self, ProtocolTypeError> {
@@ -114,7 +283,8 @@ impl FromStr for ReviewProtocolType {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Default, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option), default)]
 pub struct DefaultTag {
     remote: Option<String>,
     revision: Option<String>,
@@ -124,60 +294,184 @@ pub struct DefaultTag {
 
     upstream: Option<String>,
 
-    #[serde(rename = "sync-j")]
-    sync_j: Option<String>,
+    #[serde(rename = "sync-j", default, deserialize_with = "sync_j_u32")]
+    sync_j: Option<u32>,
+
+    #[serde(rename = "sync-c", default, deserialize_with = "sync_c_bool")]
+    sync_c: Option<bool>,
 
-    #[serde(rename = "sync-c")]
-    sync_c: Option<String>,
+    #[serde(rename = "sync-s", default, deserialize_with = "sync_s_bool")]
+    sync_s: Option<bool>,
 
-    #[serde(rename = "sync-s")]
-    sync_s: Option<String>,
+    #[serde(rename = "sync-tags", default, deserialize_with = "sync_tags_bool")]
+    sync_tags: Option<bool>,
+
+    #[serde(skip)]
+    #[new(default)]
+    extra: Extra,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
 pub struct ManifestServer {
     url: String,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+/// Removes the projects matching `name` and/or `path` from the manifest.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Default, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option), default)]
 pub struct RemoveProject {
-    name: String,
+    name: Option<String>,
+    path: Option<String>,
+
+    #[serde(default, deserialize_with = "optional_bool")]
+    optional: Option<bool>,
+
+    #[serde(rename = "base-rev")]
+    base_rev: Option<String>,
+
+    #[serde(skip)]
+    #[new(default)]
+    extra: Extra,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+/// Modifies the attributes of projects named `name`, or only the one at `path`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
 pub struct ExtendProject {
     name: String,
+    #[builder(default)]
+    path: Option<String>,
+
+    #[serde(rename = "dest-path")]
+    #[builder(default)]
+    dest_path: Option<String>,
+
+    #[builder(default)]
+    groups: Option<String>,
+    #[builder(default)]
+    revision: Option<String>,
+    #[builder(default)]
+    remote: Option<String>,
+
+    #[serde(rename = "dest-branch")]
+    #[builder(default)]
+    dest_branch: Option<String>,
+
+    #[builder(default)]
+    upstream: Option<String>,
+
+    #[serde(rename = "base-rev")]
+    #[builder(default)]
+    base_rev: Option<String>,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
 pub struct Project {
     name: String,
+    #[builder(default)]
     path: Option<String>,
+    #[builder(default)]
     remote: Option<String>,
+    #[builder(default)]
     revision: Option<String>,
 
     #[serde(rename = "dest-branch")]
+    #[builder(default)]
     dest_branch: Option<String>,
 
+    #[builder(default)]
     groups: Option<String>,
-    rebase: Option<String>,
 
-    #[serde(rename = "sync-c")]
-    sync_c: Option<String>,
+    #[serde(default, deserialize_with = "rebase_bool")]
+    #[builder(default)]
+    rebase: Option<bool>,
+
+    #[serde(rename = "sync-c", default, deserialize_with = "sync_c_bool")]
+    #[builder(default)]
+    sync_c: Option<bool>,
 
-    #[serde(rename = "sync-s")]
-    sync_s: Option<String>,
+    #[serde(rename = "sync-s", default, deserialize_with = "sync_s_bool")]
+    #[builder(default)]
+    sync_s: Option<bool>,
 
-    #[serde(rename = "sync-tags")]
-    sync_tags: Option<String>,
+    #[serde(rename = "sync-tags", default, deserialize_with = "sync_tags_bool")]
+    #[builder(default)]
+    sync_tags: Option<bool>,
 
+    #[builder(default)]
     upstream: Option<String>,
 
-    #[serde(rename = "clone-depth")]
-    clone_depth: Option<usize>,
+    #[serde(rename = "clone-depth", default, deserialize_with = "clone_depth_u32")]
+    #[builder(default)]
+    clone_depth: Option<u32>,
+
+    #[serde(rename = "force-path", default, deserialize_with = "force_path_bool")]
+    #[builder(default)]
+    force_path: Option<bool>,
+
+    #[serde(rename = "linkfile", default)]
+    #[builder(default, setter(each(name = "linkfile", into)))]
+    linkfiles: Vec<LinkFile>,
+
+    #[serde(rename = "copyfile", default)]
+    #[builder(default, setter(each(name = "copyfile", into)))]
+    copyfiles: Vec<CopyFile>,
+
+    #[serde(rename = "annotation", default)]
+    #[builder(default, setter(each(name = "annotation", into)))]
+    annotations: Vec<Annotation>,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
+}
+
+impl Project {
+    /// The checkout path relative to the top of the workspace, repo uses the
+    /// project name when no `path` is given.
+    pub fn relpath(&self) -> &str {
+        self.path.as_deref().unwrap_or(&self.name)
+    }
+}
 
-    #[serde(rename = "force-path")]
-    force_path: Option<String>,
+/// A `<linkfile>` child of a project, `src` is relative to the project and
+/// `dest` is relative to the top of the checkout.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
+pub struct LinkFile {
+    src: String,
+    dest: String,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
+}
+
+/// A `<copyfile>` child of a project, paths are interpreted like `LinkFile`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
+pub struct CopyFile {
+    src: String,
+    dest: String,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
 }
 
 fn deserialize_space_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
@@ -189,18 +483,173 @@ where
     Ok(buf.split_whitespace().map(|s| s.to_string()).collect())
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+fn serialize_space_separated<S>(values: &[String], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&values.join(" "))
+}
+
+/// Parses a boolean attribute using repo's lenient rules, which accept
+/// `yes`/`true`/`1` and `no`/`false`/`0` in any case.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn deserialize_bool<'de, D>(deserializer: D, attribute: &str) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let buf = String::deserialize(deserializer)?;
+    match parse_bool(&buf) {
+        Some(value) => Ok(Some(value)),
+        None => Err(D::Error::custom(format!(
+            "invalid value \"{}\" for attribute `{}`, expected a boolean",
+            buf, attribute
+        ))),
+    }
+}
+
+fn deserialize_u32<'de, D>(deserializer: D, attribute: &str) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let buf = String::deserialize(deserializer)?;
+    match buf.trim().parse() {
+        Ok(value) => Ok(Some(value)),
+        Err(_) => Err(D::Error::custom(format!(
+            "invalid value \"{}\" for attribute `{}`, expected a non-negative integer",
+            buf, attribute
+        ))),
+    }
+}
+
+// serde's `deserialize_with` has no access to the field name, so each typed
+// attribute gets a function which names it in errors.
+macro_rules! typed_attributes {
+    ($($function:ident: $attribute:literal => $deserialize:ident -> $ty:ty,)*) => {
+        $(
+            fn $function<'de, D>(deserializer: D) -> Result<Option<$ty>, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                $deserialize(deserializer, $attribute)
+            }
+        )*
+    };
+}
+
+typed_attributes! {
+    override_bool: "override" => deserialize_bool -> bool,
+    keep_bool: "keep" => deserialize_bool -> bool,
+    optional_bool: "optional" => deserialize_bool -> bool,
+    rebase_bool: "rebase" => deserialize_bool -> bool,
+    sync_c_bool: "sync-c" => deserialize_bool -> bool,
+    sync_s_bool: "sync-s" => deserialize_bool -> bool,
+    sync_tags_bool: "sync-tags" => deserialize_bool -> bool,
+    force_path_bool: "force-path" => deserialize_bool -> bool,
+    sync_j_u32: "sync-j" => deserialize_u32 -> u32,
+    clone_depth_u32: "clone-depth" => deserialize_u32 -> u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
 pub struct RepoHooks {
     #[serde(rename = "in-project")]
     in_project: String,
     #[serde(
         rename = "enabled-list",
-        deserialize_with = "deserialize_space_separated"
+        deserialize_with = "deserialize_space_separated",
+        serialize_with = "serialize_space_separated"
     )]
+    #[builder(default)]
     enabled_list: Vec<String>,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, new)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
 pub struct Include {
     name: String,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
+}
+
+/// The repository whose submodules track every project's revision, `remote`
+/// and `revision` are inherited like a project's.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
+pub struct Superproject {
+    name: String,
+    #[builder(default)]
+    remote: Option<String>,
+    #[builder(default)]
+    revision: Option<String>,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
+}
+
+/// A nested workspace checked out at `path`, whose manifest `manifest-name`
+/// comes from the manifest repository `project` on `remote`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
+pub struct Submanifest {
+    name: String,
+    #[builder(default)]
+    remote: Option<String>,
+    #[builder(default)]
+    project: Option<String>,
+    #[builder(default)]
+    revision: Option<String>,
+
+    #[serde(rename = "manifest-name")]
+    #[builder(default)]
+    manifest_name: Option<String>,
+
+    #[builder(default)]
+    path: Option<String>,
+    #[builder(default)]
+    groups: Option<String>,
+
+    #[serde(rename = "default-groups")]
+    #[builder(default)]
+    default_groups: Option<String>,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
+}
+
+impl Submanifest {
+    /// The checkout path relative to the outer workspace, `name` by default.
+    pub fn relpath(&self) -> &str {
+        self.path.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Where to report problems with the manifest.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Getters, Builder, new)]
+#[builder(pattern = "owned", setter(into, strip_option))]
+pub struct ContactInfo {
+    bugurl: String,
+
+    #[serde(skip)]
+    #[new(default)]
+    #[builder(default)]
+    extra: Extra,
 }
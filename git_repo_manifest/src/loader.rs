@@ -0,0 +1,350 @@
+use crate::diagnostic::{parse_str, ParseError};
+use crate::resolve::{extend_project, remove_project};
+use crate::{ExtendProject, Manifest, Project, RemoveProject, ResolveError};
+use quick_error::quick_error;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::path::{Path, PathBuf};
+use std::{ffi, fmt, fs, io};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum LoadError {
+        Io(path: PathBuf, err: io::Error) {
+            display("{}: {}", path.display(), err)
+            source(err)
+        }
+        Parse(err: ParseError) {
+            display("{}", err)
+            source(err)
+        }
+        IncludeCycle(chain: IncludeChain) {
+            display("include cycle: {}", chain)
+        }
+        Duplicate(path: PathBuf, element: String) {
+            display("{}: duplicate {} conflicts with an earlier definition", path.display(), element)
+        }
+        Include(path: PathBuf, err: Box<LoadError>) {
+            display("{}\n  = note: included from {}", err, path.display())
+            source(err)
+        }
+        Resolve(path: PathBuf, err: ResolveError) {
+            display("{}: {}", path.display(), err)
+            source(err)
+        }
+        Submanifest(name: String, err: Box<LoadError>) {
+            display("{}\n  = note: in submanifest {}", err, name)
+            source(err)
+        }
+    }
+}
+
+/// The files being loaded, outermost first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncludeChain(pub Vec<PathBuf>);
+
+impl fmt::Display for IncludeChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self.0.iter().map(|p| p.display().to_string()).collect();
+        write!(f, "{}", names.join(" -> "))
+    }
+}
+
+/// Parses the manifest at `path` without following any includes.
+pub fn parse_file(path: &Path) -> Result<Manifest, LoadError> {
+    let source = fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))?;
+    parse_str(&source, path).map_err(LoadError::Parse)
+}
+
+/// A child of the root element whose position among its siblings matters.
+enum Ordered {
+    Include,
+    Project,
+    RemoveProject,
+    ExtendProject,
+}
+
+/// The `<include>`, `<project>`, `<remove-project>` and `<extend-project>`
+/// children of the root element of `source`, in document order.
+fn ordered_elements(source: &str) -> Vec<Ordered> {
+    let mut reader = Reader::from_str(source);
+    let mut buf = Vec::new();
+    let mut depth = 0;
+    let mut order = Vec::new();
+    loop {
+        let name = match reader.read_event(&mut buf) {
+            Ok(Event::Start(start)) => {
+                depth += 1;
+                Some(start.name().to_vec()).filter(|_| depth == 2)
+            }
+            Ok(Event::Empty(start)) => Some(start.name().to_vec()).filter(|_| depth == 1),
+            Ok(Event::End(_)) => {
+                depth -= 1;
+                None
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => None,
+        };
+        match name.as_deref() {
+            Some(b"include") => order.push(Ordered::Include),
+            Some(b"project") => order.push(Ordered::Project),
+            Some(b"remove-project") => order.push(Ordered::RemoveProject),
+            Some(b"extend-project") => order.push(Ordered::ExtendProject),
+            _ => (),
+        }
+        buf.clear();
+    }
+    order
+}
+
+/// A `<project>`, `<remove-project>` or `<extend-project>`, with the file the
+/// latter two came from.
+enum Step {
+    Project(Project),
+    Remove(PathBuf, RemoveProject),
+    Extend(PathBuf, ExtendProject),
+}
+
+/// Applies `steps` in order, once every remote and `<default>` is known.
+fn apply_steps(manifest: &mut Manifest, steps: Vec<Step>) -> Result<(), LoadError> {
+    let default = manifest.default.as_ref();
+    for step in steps {
+        match step {
+            Step::Project(project) => manifest.projects.push(project),
+            Step::Remove(path, remove) => {
+                remove_project(&mut manifest.projects, &remove, &manifest.remotes, default)
+                    .map_err(|err| LoadError::Resolve(path, err))?
+            }
+            Step::Extend(path, extend) => {
+                extend_project(&mut manifest.projects, &extend, &manifest.remotes, default)
+                    .map_err(|err| LoadError::Resolve(path, err))?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Loads `path` and recursively inlines every `<include>`.
+///
+/// Like repo, include names are relative to `manifest_dir` rather than to the
+/// including file. Included projects take the place of their `<include>`,
+/// while other included elements are merged ahead of those of the including
+/// manifest. `<remove-project>` and `<extend-project>` apply in document
+/// order, so a project can be removed and then defined anew. The result has
+/// no remaining includes, removes or extends.
+pub fn load(manifest_dir: &Path, path: &Path) -> Result<Manifest, LoadError> {
+    let mut manifest = <Manifest as Default>::default();
+    let mut steps = Vec::new();
+    load_recursive(
+        manifest_dir,
+        path,
+        &mut Vec::new(),
+        &mut manifest,
+        &mut steps,
+    )?;
+    apply_steps(&mut manifest, steps)?;
+    Ok(manifest)
+}
+
+/// Merges `path` and its includes into `merged`, all but the elements kept
+/// in document order in `steps`.
+fn load_recursive(
+    manifest_dir: &Path,
+    path: &Path,
+    chain: &mut Vec<(PathBuf, PathBuf)>,
+    merged: &mut Manifest,
+    steps: &mut Vec<Step>,
+) -> Result<(), LoadError> {
+    let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if chain.iter().any(|(k, _)| *k == key) {
+        let mut cycle: Vec<PathBuf> = chain.iter().map(|(_, p)| p.clone()).collect();
+        cycle.push(path.to_path_buf());
+        return Err(LoadError::IncludeCycle(IncludeChain(cycle)));
+    }
+
+    let source = fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))?;
+    let mut manifest = parse_str(&source, path).map_err(LoadError::Parse)?;
+    let mut includes = std::mem::take(&mut manifest.includes).into_iter();
+    let mut projects = std::mem::take(&mut manifest.projects).into_iter();
+    let mut removes = std::mem::take(&mut manifest.remove_projects).into_iter();
+    let mut extends = std::mem::take(&mut manifest.extend_projects).into_iter();
+    chain.push((key, path.to_path_buf()));
+    for element in ordered_elements(&source) {
+        match element {
+            Ordered::Include => {
+                if let Some(include) = includes.next() {
+                    let include_path = manifest_dir.join(include.name());
+                    load_recursive(manifest_dir, &include_path, chain, merged, steps)
+                        .map_err(|err| LoadError::Include(path.to_path_buf(), Box::new(err)))?;
+                }
+            }
+            Ordered::Project => steps.extend(projects.next().map(Step::Project)),
+            Ordered::RemoveProject => {
+                steps.extend(removes.next().map(|r| Step::Remove(path.to_path_buf(), r)))
+            }
+            Ordered::ExtendProject => {
+                steps.extend(extends.next().map(|e| Step::Extend(path.to_path_buf(), e)))
+            }
+        }
+    }
+    chain.pop();
+    merged.merge(manifest, path)
+}
+
+/// Loads `path` like `load`, then overlays each `*.xml` file found in
+/// `local_manifest_dir` in file name order, as `repo sync` does.
+///
+/// The elements of the local manifests follow those of the main manifest in
+/// document order, so a local `<remove-project>` can drop a project of the
+/// main manifest, and a local `<project>` define it anew. Includes within
+/// local manifests are relative to the parent of `local_manifest_dir`, and
+/// their projects gain a `local:<file stem>` group. A missing
+/// `local_manifest_dir` is not an error.
+pub fn load_with_local_manifests(
+    manifest_dir: &Path,
+    path: &Path,
+    local_manifest_dir: &Path,
+) -> Result<Manifest, LoadError> {
+    let mut manifest = <Manifest as Default>::default();
+    let mut steps = Vec::new();
+    load_recursive(
+        manifest_dir,
+        path,
+        &mut Vec::new(),
+        &mut manifest,
+        &mut steps,
+    )?;
+
+    let mut local_paths = Vec::new();
+    match fs::read_dir(local_manifest_dir) {
+        Ok(dirs) => {
+            for dir_entry in dirs {
+                let dir_entry = dir_entry
+                    .map_err(|err| LoadError::Io(local_manifest_dir.to_path_buf(), err))?;
+                let path = dir_entry.path();
+                if path.extension().and_then(ffi::OsStr::to_str) == Some("xml") {
+                    local_paths.push(path);
+                }
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => return Err(LoadError::Io(local_manifest_dir.to_path_buf(), err)),
+    }
+    local_paths.sort();
+
+    let include_root = local_manifest_dir.parent().unwrap_or(local_manifest_dir);
+    for local_path in local_paths {
+        let mut local = <Manifest as Default>::default();
+        let mut local_steps = Vec::new();
+        load_recursive(
+            include_root,
+            &local_path,
+            &mut Vec::new(),
+            &mut local,
+            &mut local_steps,
+        )?;
+        if let Some(stem) = local_path.file_stem().and_then(ffi::OsStr::to_str) {
+            let group = format!("local:{}", stem);
+            for step in &mut local_steps {
+                if let Step::Project(project) = step {
+                    project.groups = match project.groups.take() {
+                        Some(groups) => Some(format!("{},{}", groups, group)),
+                        None => Some(group.clone()),
+                    };
+                }
+            }
+        }
+        manifest.override_remotes(&mut local);
+        manifest.merge(local, &local_path)?;
+        steps.extend(local_steps);
+    }
+    apply_steps(&mut manifest, steps)?;
+    Ok(manifest)
+}
+
+fn merge_unique<T: PartialEq>(
+    into: &mut Option<T>,
+    from: Option<T>,
+    path: &Path,
+    element: &str,
+) -> Result<(), LoadError> {
+    match (into.as_ref(), from) {
+        (_, None) => Ok(()),
+        (None, from) => {
+            *into = from;
+            Ok(())
+        }
+        (Some(existing), Some(from)) if *existing == from => Ok(()),
+        (Some(_), Some(_)) => Err(LoadError::Duplicate(
+            path.to_path_buf(),
+            element.to_string(),
+        )),
+    }
+}
+
+impl Manifest {
+    /// Appends the elements of `other` onto `self`, `path` names `other` in errors.
+    ///
+    /// Remotes may be repeated only with identical attributes, and there may be
+    /// at most one distinct `<default>`, `<notice>`, `<manifest-server>`,
+    /// `<repo-hooks>`, `<superproject>` and `<contactinfo>`.
+    pub fn merge(&mut self, other: Manifest, path: &Path) -> Result<(), LoadError> {
+        merge_unique(&mut self.notice, other.notice, path, "<notice>")?;
+        merge_unique(
+            &mut self.manifest_server,
+            other.manifest_server,
+            path,
+            "<manifest-server>",
+        )?;
+        merge_unique(&mut self.default, other.default, path, "<default>")?;
+        merge_unique(&mut self.repo_hooks, other.repo_hooks, path, "<repo-hooks>")?;
+        merge_unique(
+            &mut self.superproject,
+            other.superproject,
+            path,
+            "<superproject>",
+        )?;
+        merge_unique(
+            &mut self.contactinfo,
+            other.contactinfo,
+            path,
+            "<contactinfo>",
+        )?;
+
+        for remote in other.remotes {
+            match self.remotes.iter().find(|r| r.name == remote.name) {
+                Some(existing) if *existing == remote => (),
+                Some(_) => {
+                    return Err(LoadError::Duplicate(
+                        path.to_path_buf(),
+                        format!("<remote name=\"{}\">", remote.name),
+                    ))
+                }
+                None => self.remotes.push(remote),
+            }
+        }
+        self.remove_projects.extend(other.remove_projects);
+        self.projects.extend(other.projects);
+        self.extend_projects.extend(other.extend_projects);
+        self.submanifests.extend(other.submanifests);
+        self.includes.extend(other.includes);
+        self.extra.extend(other.extra);
+        Ok(())
+    }
+
+    /// Moves the remotes of `local` with `override="true"` into `self`,
+    /// replacing any of the same name.
+    fn override_remotes(&mut self, local: &mut Manifest) {
+        let (overrides, remotes) = local
+            .remotes
+            .drain(..)
+            .partition(|remote| remote.r#override == Some(true));
+        local.remotes = remotes;
+        for remote in overrides {
+            match self.remotes.iter_mut().find(|r| r.name == remote.name) {
+                Some(existing) => *existing = remote,
+                None => self.remotes.push(remote),
+            }
+        }
+    }
+}
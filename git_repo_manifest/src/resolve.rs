@@ -0,0 +1,154 @@
+use crate::{DefaultTag, ExtendProject, Manifest, Project, Remote, RemoveProject};
+use quick_error::quick_error;
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum ResolveError {
+        RemoveWithoutNameOrPath {
+            display("<remove-project> must have a name and/or a path")
+        }
+        RemoveNonexistent(name_or_path: String) {
+            display("<remove-project> specifies non-existent project: {}", name_or_path)
+        }
+        ExtendNonexistent(name: String) {
+            display("<extend-project> specifies non-existent project: {}", name)
+        }
+        BaseRevMismatch(name: String, expected: String, actual: Option<String>) {
+            display("revision base check failed for {}, expected {}, got {}",
+                name, expected, actual.as_deref().unwrap_or("no revision"))
+        }
+    }
+}
+
+/// The revision `project` checks out: its own, its remote's or the `<default>`
+/// one, as `set_defaults` fills it in.
+fn effective_revision<'a>(
+    project: &'a Project,
+    remotes: &'a [Remote],
+    default: Option<&'a DefaultTag>,
+) -> Option<&'a String> {
+    let remote_name = project.remote.as_ref().or_else(|| default?.remote.as_ref());
+    project
+        .revision
+        .as_ref()
+        .or_else(|| {
+            let remote = remotes.iter().find(|r| Some(&r.name) == remote_name)?;
+            remote.revision.as_ref()
+        })
+        .or_else(|| default?.revision.as_ref())
+}
+
+fn check_base_rev(
+    project: &Project,
+    base_rev: &Option<String>,
+    remotes: &[Remote],
+    default: Option<&DefaultTag>,
+) -> Result<(), ResolveError> {
+    if let Some(base_rev) = base_rev {
+        let revision = effective_revision(project, remotes, default);
+        if revision != Some(base_rev) {
+            return Err(ResolveError::BaseRevMismatch(
+                project.name.clone(),
+                base_rev.clone(),
+                revision.cloned(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Removes every project in `projects` matched by `remove`.
+pub(crate) fn remove_project(
+    projects: &mut Vec<Project>,
+    remove: &RemoveProject,
+    remotes: &[Remote],
+    default: Option<&DefaultTag>,
+) -> Result<(), ResolveError> {
+    let matches = |project: &Project| match (&remove.name, &remove.path) {
+        (Some(name), Some(path)) => project.name == *name && project.relpath() == path,
+        (Some(name), None) => project.name == *name,
+        (None, Some(path)) => project.relpath() == path,
+        (None, None) => false,
+    };
+    if remove.name.is_none() && remove.path.is_none() {
+        return Err(ResolveError::RemoveWithoutNameOrPath);
+    }
+
+    for project in projects.iter().filter(|p| matches(p)) {
+        check_base_rev(project, &remove.base_rev, remotes, default)?;
+    }
+    let count = projects.len();
+    projects.retain(|p| !matches(p));
+    if count == projects.len() && remove.optional != Some(true) {
+        let name_or_path = remove.name.as_ref().or(remove.path.as_ref()).unwrap();
+        return Err(ResolveError::RemoveNonexistent(name_or_path.clone()));
+    }
+    Ok(())
+}
+
+/// Applies the attributes of `extend` to every project in `projects` it matches.
+pub(crate) fn extend_project(
+    projects: &mut [Project],
+    extend: &ExtendProject,
+    remotes: &[Remote],
+    default: Option<&DefaultTag>,
+) -> Result<(), ResolveError> {
+    let mut found = false;
+    for project in projects.iter_mut() {
+        if project.name != extend.name {
+            continue;
+        }
+        if let Some(path) = &extend.path {
+            if project.relpath() != path {
+                continue;
+            }
+        }
+        found = true;
+
+        if let Some(groups) = &extend.groups {
+            project.groups = match project.groups.take() {
+                Some(existing) => Some(format!("{},{}", existing, groups)),
+                None => Some(groups.clone()),
+            };
+        }
+        if let Some(revision) = &extend.revision {
+            check_base_rev(project, &extend.base_rev, remotes, default)?;
+            project.revision = Some(revision.clone());
+        }
+        if let Some(remote) = &extend.remote {
+            project.remote = Some(remote.clone());
+        }
+        if let Some(dest_branch) = &extend.dest_branch {
+            project.dest_branch = Some(dest_branch.clone());
+        }
+        if let Some(upstream) = &extend.upstream {
+            project.upstream = Some(upstream.clone());
+        }
+        if let Some(dest_path) = &extend.dest_path {
+            project.path = Some(dest_path.clone());
+        }
+    }
+    if !found {
+        return Err(ResolveError::ExtendNonexistent(extend.name.clone()));
+    }
+    Ok(())
+}
+
+impl Manifest {
+    /// Applies every `<remove-project>` and then every `<extend-project>` to
+    /// `projects`, leaving both lists empty.
+    ///
+    /// A parsed manifest no longer knows where these stood among its
+    /// `<project>`s, so this suits a single file written in that order. `load`
+    /// applies them in document order instead.
+    pub fn resolve_projects(&mut self) -> Result<(), ResolveError> {
+        let default = self.default.as_ref();
+        for remove in self.remove_projects.drain(..) {
+            remove_project(&mut self.projects, &remove, &self.remotes, default)?;
+        }
+        for extend in self.extend_projects.drain(..) {
+            extend_project(&mut self.projects, &extend, &self.remotes, default)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{git, Project};
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+/// A `revision`, `upstream` or `dest-branch` value, classified by what it
+/// names.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Revision {
+    /// A SHA-1 or SHA-256 commit id.
+    CommitId(String),
+    /// A branch, given either as `refs/heads/<name>` or as just `<name>`,
+    /// holding the name.
+    Branch(String),
+    /// A tag, given as `refs/tags/<name>`, holding the name.
+    Tag(String),
+    /// Any other fully qualified ref, such as `refs/changes/45/1245/3`.
+    Ref(String),
+}
+
+impl Revision {
+    /// One of `commit`, `branch`, `tag` or `ref`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Revision::CommitId(_) => "commit",
+            Revision::Branch(_) => "branch",
+            Revision::Tag(_) => "tag",
+            Revision::Ref(_) => "ref",
+        }
+    }
+
+    /// The fully qualified ref, or the commit id itself.
+    pub fn to_ref(&self) -> String {
+        match self {
+            Revision::CommitId(id) => id.clone(),
+            Revision::Branch(name) => format!("refs/heads/{}", name),
+            Revision::Tag(name) => format!("refs/tags/{}", name),
+            Revision::Ref(name) => name.clone(),
+        }
+    }
+
+    /// Whether syncing always checks out the same commit, which holds for
+    /// commit ids and, barring force pushes, tags.
+    pub fn is_pinned(&self) -> bool {
+        matches!(self, Revision::CommitId(_) | Revision::Tag(_))
+    }
+}
+
+impl FromStr for Revision {
+    type Err = Infallible;
+
+    /// Anything not otherwise recognised is a branch shorthand, as in repo.
+    fn from_str(revision: &str) -> Result<Self, Self::Err> {
+        let revision = if git::is_object_id(revision) {
+            Revision::CommitId(revision.to_string())
+        } else if let Some(branch) = revision.strip_prefix("refs/heads/") {
+            Revision::Branch(branch.to_string())
+        } else if let Some(tag) = revision.strip_prefix("refs/tags/") {
+            Revision::Tag(tag.to_string())
+        } else if revision.starts_with("refs/") {
+            Revision::Ref(revision.to_string())
+        } else {
+            Revision::Branch(revision.to_string())
+        };
+        Ok(revision)
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_ref())
+    }
+}
+
+fn classify(value: &Option<String>) -> Option<Revision> {
+    value.as_deref().map(|value| value.parse().unwrap())
+}
+
+impl Project {
+    /// The classified `revision`, after `set_defaults` the effective one.
+    pub fn typed_revision(&self) -> Option<Revision> {
+        classify(&self.revision)
+    }
+
+    pub fn typed_upstream(&self) -> Option<Revision> {
+        classify(&self.upstream)
+    }
+
+    pub fn typed_dest_branch(&self) -> Option<Revision> {
+        classify(&self.dest_branch)
+    }
+}
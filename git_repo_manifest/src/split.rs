@@ -0,0 +1,81 @@
+use crate::groups::split_groups;
+use crate::{Include, Manifest, Project};
+
+/// How `Manifest::split` assigns projects to included files.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SplitBy {
+    /// By the project's remote, falling back to the `<default>` remote.
+    Remote,
+    /// By the first group listed in the project's `groups`.
+    Group,
+}
+
+/// Projects without a remote or a group end up in `<dir>/ungrouped.xml`.
+const UNGROUPED: &str = "ungrouped";
+
+/// Keeps file names portable, `platform/linux` becomes `platform_linux`.
+fn file_stem(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+impl Manifest {
+    fn split_key(&self, project: &Project, by: SplitBy) -> Option<String> {
+        match by {
+            SplitBy::Remote => project
+                .remote
+                .as_ref()
+                .or_else(|| self.default.as_ref()?.remote.as_ref())
+                .cloned(),
+            SplitBy::Group => project
+                .groups
+                .as_deref()
+                .and_then(|groups| split_groups(groups).next())
+                .map(str::to_string),
+        }
+    }
+
+    /// Splits the manifest into a top-level manifest and one manifest per
+    /// remote or group, named `<dir>/<remote or group>.xml`, holding its
+    /// projects in their original order. Names which would clash once made
+    /// portable, such as `platform/linux` and `platform_linux`, are told
+    /// apart by a numeric suffix, `platform_linux-2.xml`.
+    ///
+    /// The top-level manifest keeps every other element and `<include>`s
+    /// each of the returned files in turn, so that loading it gives back the
+    /// same projects. `<remove-project>` and `<extend-project>` stay in the
+    /// top-level manifest, written ahead of the `<include>`s. repo applies
+    /// them in document order, before the projects they name are included,
+    /// so resolve them first, as `load` does.
+    pub fn split(mut self, by: SplitBy, dir: &str) -> (Manifest, Vec<(String, Manifest)>) {
+        let mut keys: Vec<Option<String>> = Vec::new();
+        let mut parts: Vec<(String, Manifest)> = Vec::new();
+        for project in std::mem::take(&mut self.projects) {
+            let key = self.split_key(&project, by);
+            match keys.iter().position(|k| *k == key) {
+                Some(index) => parts[index].1.projects.push(project),
+                None => {
+                    let stem = file_stem(key.as_deref().unwrap_or(UNGROUPED));
+                    let mut name = format!("{}/{}.xml", dir, stem);
+                    let mut suffix = 1;
+                    while parts.iter().any(|(part, _)| *part == name) {
+                        suffix += 1;
+                        name = format!("{}/{}-{}.xml", dir, stem, suffix);
+                    }
+                    let mut part = <Manifest as Default>::default();
+                    part.projects.push(project);
+                    keys.push(key);
+                    parts.push((name, part));
+                }
+            }
+        }
+        for (name, _) in &parts {
+            self.includes.push(Include::new(name.clone()));
+        }
+        (self, parts)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{load, LoadError, Manifest};
+use derive_getters::{Dissolve, Getters};
+use std::path::{Path, PathBuf};
+
+/// The manifest of a `<submanifest>`, with its projects' paths relative to
+/// the top of the outermost workspace.
+#[derive(Debug, Getters, Dissolve)]
+pub struct LoadedSubmanifest {
+    /// Checkout path relative to the outermost workspace.
+    path: String,
+    /// The submanifest's own `.repo` directory.
+    repo_dir: PathBuf,
+    manifest: Manifest,
+}
+
+impl Manifest {
+    /// Prepends `prefix` to every project's path and `<linkfile>`/`<copyfile>`
+    /// destination, for projects of a workspace nested at `prefix`.
+    pub fn prefix_paths(&mut self, prefix: &str) {
+        let prefix = prefix.trim_end_matches('/');
+        if prefix.is_empty() {
+            return;
+        }
+        let join = |path: &str| format!("{}/{}", prefix, path);
+        for project in &mut self.projects {
+            project.path = Some(join(project.relpath()));
+            for link in &mut project.linkfiles {
+                link.dest = join(&link.dest);
+            }
+            for copy in &mut project.copyfiles {
+                copy.dest = join(&copy.dest);
+            }
+        }
+    }
+}
+
+/// Loads every `<submanifest>` of `manifest`, and theirs in turn, depth first.
+///
+/// As with repo, a submanifest at `path` keeps its manifest checkout in
+/// `<repo_dir>/submanifests/<path>/manifests`, where `repo_dir` is the `.repo`
+/// directory of the workspace declaring it, and reads `manifest-name`, which
+/// defaults to `default.xml`. Each manifest has its includes, `<remove-project>`s
+/// and `<extend-project>`s resolved, and its paths prefixed as in `prefix_paths`.
+/// A submanifest's `groups` are added to each of its projects.
+pub fn load_submanifests(
+    repo_dir: &Path,
+    manifest: &Manifest,
+) -> Result<Vec<LoadedSubmanifest>, LoadError> {
+    let mut loaded = Vec::new();
+    load_recursive(repo_dir, "", manifest, &mut loaded)?;
+    Ok(loaded)
+}
+
+fn load_recursive(
+    repo_dir: &Path,
+    outer_path: &str,
+    manifest: &Manifest,
+    loaded: &mut Vec<LoadedSubmanifest>,
+) -> Result<(), LoadError> {
+    for submanifest in &manifest.submanifests {
+        let sub_repo_dir = repo_dir.join("submanifests").join(submanifest.relpath());
+        let manifest_dir = sub_repo_dir.join("manifests");
+        let manifest_name = submanifest
+            .manifest_name
+            .as_deref()
+            .unwrap_or("default.xml");
+        let manifest_path = manifest_dir.join(manifest_name);
+        let in_submanifest = |err| LoadError::Submanifest(submanifest.name.clone(), Box::new(err));
+        let mut sub = load(&manifest_dir, &manifest_path).map_err(in_submanifest)?;
+
+        let path = match outer_path {
+            "" => submanifest.relpath().to_string(),
+            outer => format!("{}/{}", outer, submanifest.relpath()),
+        };
+        if let Some(groups) = &submanifest.groups {
+            for project in &mut sub.projects {
+                project.groups = match project.groups.take() {
+                    Some(existing) => Some(format!("{},{}", existing, groups)),
+                    None => Some(groups.clone()),
+                };
+            }
+        }
+        // Nested submanifests are relative to this one.
+        let mut nested = Vec::new();
+        load_recursive(&sub_repo_dir, &path, &sub, &mut nested).map_err(in_submanifest)?;
+        sub.prefix_paths(&path);
+        loaded.push(LoadedSubmanifest {
+            path,
+            repo_dir: sub_repo_dir,
+            manifest: sub,
+        });
+        loaded.append(&mut nested);
+    }
+    Ok(())
+}
@@ -1,7 +1,16 @@
-use crate::Manifest;
-use insta::{assert_debug_snapshot, glob};
+use crate::{
+    apply_gitlinks, format_manifest, load, load_submanifests, load_with_local_manifests,
+    parse_file, parse_gitmodules, parse_str, pin_script, resolve_url, write_gitmodules,
+    AnnotationBuilder, Change, DefaultTagBuilder, Document, EditError, FreezeError,
+    GitmodulesError, GroupFilter, IncludeChain, LinkFileBuilder, LoadError, Manifest,
+    ManifestBuilder, ProjectBuilder, RemoteBuilder, ResolveError, Revision, SplitBy, Submodule,
+    ValidationError, JSON_SCHEMA,
+};
+use insta::{assert_debug_snapshot, assert_snapshot, glob};
 use quick_xml::de::{from_str, DeError};
+use serde_json::Value;
 use std::fs;
+use std::path::Path;
 #[test]
 fn run_test_inputs() {
     glob!("test_inputs/*.xml", |path| {
@@ -11,3 +20,1227 @@ fn run_test_inputs() {
         assert_debug_snapshot!(foo);
     });
 }
+
+#[test]
+fn round_trip_linkfile_copyfile() {
+    let input = r#"<manifest>
+  <project name="tools" path="tools">
+    <linkfile src="cmake-tool/griddle" dest="griddle"/>
+    <copyfile src="Makefile" dest="Makefile"/>
+  </project>
+</manifest>"#;
+    let manifest: Manifest = from_str(input).unwrap();
+    let project = &manifest.projects()[0];
+    assert_eq!(project.linkfiles()[0].dest(), "griddle");
+    assert_eq!(project.copyfiles()[0].src(), "Makefile");
+
+    let output = quick_xml::se::to_string(&manifest).unwrap();
+    let reparsed: Manifest = from_str(&output).unwrap();
+    assert_eq!(manifest, reparsed);
+}
+
+#[test]
+fn annotations() {
+    let input = r#"<manifest>
+  <remote name="origin" fetch="https://example.com/">
+    <annotation name="owner" value="infra"/>
+  </remote>
+  <project name="tools" remote="origin">
+    <annotation name="ci" value="nightly" keep="false"/>
+  </project>
+</manifest>"#;
+    let manifest: Manifest = from_str(input).unwrap();
+    let annotation = &manifest.remotes()[0].annotations()[0];
+    assert_eq!(
+        (annotation.name().as_str(), annotation.value().as_str()),
+        ("owner", "infra")
+    );
+    let annotation = &manifest.projects()[0].annotations()[0];
+    assert_eq!(annotation.keep(), &Some(false));
+
+    let output = quick_xml::se::to_string(&manifest).unwrap();
+    let reparsed: Manifest = from_str(&output).unwrap();
+    assert_eq!(manifest, reparsed);
+}
+
+#[test]
+fn load_includes() {
+    let dir = Path::new("src/test_includes");
+    let manifest = load(dir, &dir.join("default.xml"));
+
+    assert_debug_snapshot!(manifest);
+}
+
+#[test]
+fn load_include_cycle() {
+    let dir = Path::new("src/test_includes");
+    let mut err = load(dir, &dir.join("cycle_a.xml")).unwrap_err();
+    while let LoadError::Include(_, inner) = err {
+        err = *inner;
+    }
+    match err {
+        LoadError::IncludeCycle(IncludeChain(chain)) => {
+            let names: Vec<_> = chain.iter().map(|p| p.file_name().unwrap()).collect();
+            assert_eq!(names, ["cycle_a.xml", "cycle_b.xml", "cycle_a.xml"]);
+        }
+        err => panic!("expected an include cycle: {}", err),
+    }
+}
+
+#[test]
+fn load_include_missing() {
+    let dir = Path::new("src/test_includes");
+    let err = load(dir, &dir.join("missing.xml")).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("does_not_exist.xml"));
+    assert!(message.ends_with("= note: included from src/test_includes/missing.xml"));
+}
+
+#[test]
+fn resolve_remove_extend() {
+    let path = Path::new("src/test_resolve/default.xml");
+    let mut manifest = parse_file(path).unwrap();
+    manifest.resolve_projects().unwrap();
+
+    assert_debug_snapshot!(manifest.projects());
+}
+
+#[test]
+fn resolve_remove_nonexistent() {
+    let input = r#"<manifest>
+  <project name="kernel" revision="main"/>
+  <remove-project name="kernal"/>
+</manifest>"#;
+    let mut manifest: Manifest = from_str(input).unwrap();
+    assert_eq!(
+        manifest.resolve_projects(),
+        Err(ResolveError::RemoveNonexistent("kernal".to_string()))
+    );
+
+    let input = r#"<manifest>
+  <project name="kernel" revision="main"/>
+  <extend-project name="kernel" revision="v6.1" base-rev="v6.0"/>
+</manifest>"#;
+    let mut manifest: Manifest = from_str(input).unwrap();
+    assert_eq!(
+        manifest.resolve_projects(),
+        Err(ResolveError::BaseRevMismatch(
+            "kernel".to_string(),
+            "v6.0".to_string(),
+            Some("main".to_string())
+        ))
+    );
+}
+
+#[test]
+fn resolve_in_document_order() {
+    let dir = Path::new("src/test_resolve");
+    let manifest = load(dir, &dir.join("redefine.xml")).unwrap();
+    assert!(manifest.remove_projects().is_empty());
+    assert!(manifest.extend_projects().is_empty());
+    let projects: Vec<_> = manifest
+        .projects()
+        .iter()
+        .map(|p| {
+            (
+                p.name().as_str(),
+                p.remote().as_deref(),
+                p.groups().as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        projects,
+        vec![
+            ("kernel", None, None),
+            ("tools", Some("fork"), Some("forked"))
+        ]
+    );
+
+    // base-rev is checked against the remote's revision before the default's.
+    let input = r#"<manifest>
+  <remote name="origin" fetch="https://example.com/" revision="stable"/>
+  <default remote="origin" revision="main"/>
+  <project name="kernel"/>
+  <extend-project name="kernel" revision="v6.1" base-rev="main"/>
+</manifest>"#;
+    let mut manifest: Manifest = from_str(input).unwrap();
+    assert_eq!(
+        manifest.resolve_projects(),
+        Err(ResolveError::BaseRevMismatch(
+            "kernel".to_string(),
+            "main".to_string(),
+            Some("stable".to_string())
+        ))
+    );
+}
+
+#[test]
+fn load_local_manifests() {
+    let dir = Path::new("src/test_local");
+    let manifest = load_with_local_manifests(
+        &dir.join("manifests"),
+        &dir.join("manifests/default.xml"),
+        &dir.join("local_manifests"),
+    );
+
+    assert_debug_snapshot!(manifest);
+}
+
+#[test]
+fn set_defaults() {
+    let input = r#"<manifest>
+  <remote name="origin" fetch="https://example.com/"/>
+  <remote name="stable" fetch="https://example.com/" revision="stable"/>
+  <default remote="origin" revision="main" dest-branch="main" sync-c="true" sync-tags="false"/>
+  <project name="a"/>
+  <project name="b" remote="stable" sync-c="false"/>
+  <project name="c" remote="stable" revision="v1.0" upstream="release"/>
+</manifest>"#;
+    let mut manifest: Manifest = from_str(input).unwrap();
+    manifest.set_defaults();
+    let effective: Vec<_> = manifest
+        .projects()
+        .iter()
+        .map(|p| {
+            (
+                p.remote().as_deref(),
+                p.revision().as_deref(),
+                p.dest_branch().as_deref(),
+                p.upstream().as_deref(),
+                *p.sync_c(),
+                *p.sync_tags(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        effective,
+        [
+            (
+                Some("origin"),
+                Some("main"),
+                Some("main"),
+                None,
+                Some(true),
+                Some(false)
+            ),
+            (
+                Some("stable"),
+                Some("stable"),
+                Some("main"),
+                None,
+                Some(false),
+                Some(false)
+            ),
+            (
+                Some("stable"),
+                Some("v1.0"),
+                Some("main"),
+                Some("release"),
+                Some(true),
+                Some(false)
+            ),
+        ]
+    );
+}
+
+#[test]
+fn validate() {
+    let input = r#"<manifest>
+  <remote name="origin" fetch="https://example.com/"/>
+  <remote name="origin" fetch="https://example.org/"/>
+  <default remote="upstream"/>
+  <project name="kernel" path="kernel" remote="origin"/>
+  <project name="kernel" path="kernel" remote="origin"/>
+  <project name="drivers" path="kernel/drivers" remote="origin"/>
+  <project name="kernel-headers" path="kernel-headers" remote="origin"/>
+  <project name="tools" remote="mirror" clone-depth="0"/>
+  <project name="docs" path="tools" remote="origin" clone-depth="1"/>
+  <repo-hooks in-project="hooks" enabled-list="pre-upload"/>
+</manifest>"#;
+    let manifest: Manifest = from_str(input).unwrap();
+
+    assert_debug_snapshot!(manifest.validate());
+    let warnings: Vec<_> = manifest
+        .validate()
+        .into_iter()
+        .filter(|e| !e.is_fatal())
+        .collect();
+    assert_eq!(
+        warnings,
+        vec![ValidationError::NestedPath(
+            "kernel".into(),
+            "kernel/drivers".into()
+        ),]
+    );
+}
+
+#[test]
+fn validate_test_inputs() {
+    let dir = Path::new("src/test_includes");
+    let manifest = load(dir, &dir.join("default.xml")).unwrap();
+    assert_eq!(manifest.validate(), []);
+}
+
+#[test]
+fn parse_diagnostics() {
+    let path = Path::new("manifest.xml");
+    let source = "<manifest>
+\t<remote name=\"origin\" fetch=\"https://example.com/\" override=\"maybe\"/>
+</manifest>";
+    let err = parse_str(source, path).unwrap_err();
+    assert_eq!((err.line(), err.column()), (2, 53));
+    assert_eq!(
+        (err.element(), err.attribute()),
+        (Some("remote"), Some("override"))
+    );
+    assert_snapshot!(err.to_string(), @r###"
+    error: invalid value "maybe" for attribute `override`, expected a boolean
+     --> manifest.xml:2:53
+      |
+    2 |  <remote name="origin" fetch="https://example.com/" override="maybe"/>
+      |                                                     ^^^^^^^^^^^^^^^^ attribute `override` of <remote>
+    "###);
+
+    let source = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- <project path="commented"/> -->
+<manifest>
+  <project name="kernel">
+    <linkfile src="a" dest="b"/>
+  </project>
+  <project path="tools">
+    <linkfile src="c" dest="d"/>
+  </project>
+</manifest>"#;
+    let err = parse_str(source, path).unwrap_err();
+    assert_snapshot!(err.to_string(), @r###"
+    error: missing field `name`
+     --> manifest.xml:7:4
+      |
+    7 |   <project path="tools">
+      |    ^^^^^^^ attribute `name` of <project>
+    "###);
+}
+
+#[test]
+fn typed_attributes() {
+    let input = r#"<manifest>
+  <default sync-j="4" sync-c="Yes" sync-s="0"/>
+  <project name="kernel" rebase="FALSE" sync-tags="1" force-path="true" clone-depth="2"/>
+</manifest>"#;
+    let manifest: Manifest = from_str(input).unwrap();
+    let default = manifest.default().as_ref().unwrap();
+    assert_eq!(
+        (default.sync_j(), default.sync_c(), default.sync_s()),
+        (&Some(4), &Some(true), &Some(false))
+    );
+    let project = &manifest.projects()[0];
+    assert_eq!(
+        (project.rebase(), project.sync_tags(), project.force_path()),
+        (&Some(false), &Some(true), &Some(true))
+    );
+    assert_eq!(project.clone_depth(), &Some(2));
+
+    let output = quick_xml::se::to_string(&manifest).unwrap();
+    assert!(output.contains(r#"<default sync-j="4" sync-c="true" sync-s="false"/>"#));
+    assert!(output.contains(r#"rebase="false" sync-tags="true" clone-depth="2" force-path="true""#));
+
+    let input = r#"<manifest>
+  <default sync-j="four"/>
+</manifest>"#;
+    let err = parse_str(input, Path::new("manifest.xml")).unwrap_err();
+    assert_eq!(err.attribute(), Some("sync-j"));
+    assert_eq!(
+        err.de_error().to_string(),
+        r#"invalid value "four" for attribute `sync-j`, expected a non-negative integer"#
+    );
+
+    let input = r#"<manifest>
+  <project name="tools" clone-depth="shallow"/>
+</manifest>"#;
+    let err = parse_str(input, Path::new("manifest.xml")).unwrap_err();
+    assert_eq!(err.attribute(), Some("clone-depth"));
+}
+
+#[test]
+fn resolve_fetch_urls() {
+    let manifest_url = "https://github.com/seL4/camkes-vm-manifest.git";
+    let cases = [
+        ("../seL4", "https://github.com/seL4"),
+        ("../sel4proj/", "https://github.com/sel4proj"),
+        ("..", "https://github.com"),
+        (".", "https://github.com/seL4"),
+        ("/mirror/seL4", "https://github.com/mirror/seL4"),
+        ("https://github.com/zeromq", "https://github.com/zeromq"),
+        ("git@example.com:zeromq", "git@example.com:zeromq"),
+    ];
+    for (fetch, expected) in cases.iter() {
+        assert_eq!(resolve_url(manifest_url, fetch), *expected, "{}", fetch);
+    }
+    assert_eq!(
+        resolve_url("git@example.com:platform/manifest.git", ".."),
+        "git@example.com:platform"
+    );
+    assert_eq!(
+        resolve_url("/srv/git/platform/manifest.git/", ".."),
+        "/srv/git"
+    );
+
+    let git_dir = Path::new("src/test_git/manifests.git");
+    let origin = crate::git::origin_url(git_dir).unwrap().unwrap();
+    assert_eq!(origin, manifest_url);
+
+    let input = fs::read_to_string("src/test_inputs/camkes_vm_manifest.xml").unwrap();
+    let mut manifest: Manifest = from_str(&input).unwrap();
+    manifest.set_defaults();
+    let kernel = &manifest.projects()[12];
+    assert_eq!(
+        manifest.clone_url(kernel, Some(&origin)).as_deref(),
+        Some("https://github.com/seL4/seL4.git")
+    );
+    manifest.resolve_fetch_urls(&origin);
+    let fetch: Vec<_> = manifest
+        .remotes()
+        .iter()
+        .map(|r| r.fetch().as_str())
+        .collect();
+    assert_eq!(
+        fetch,
+        [
+            "https://github.com/seL4",
+            "https://github.com/sel4proj",
+            "https://github.com/tass-belgium",
+            "https://github.com/ruslo",
+            "https://github.com/zeromq",
+        ]
+    );
+}
+
+#[test]
+fn document_edits() {
+    let path = Path::new("src/test_inputs/camkes_vm_manifest.xml");
+    let input = fs::read_to_string(path).unwrap();
+    let mut doc = Document::parse(input.clone()).unwrap();
+    assert_eq!(doc.to_string(), input);
+    assert_eq!(doc.elements("remote").count(), 5);
+
+    let kernel = doc.find("project", "name", "seL4.git").unwrap();
+    let old = "63432c91d54f9a1a1ab1ed278f788ba64ec6dba8";
+    assert_eq!(doc.attribute(kernel, "revision").as_deref(), Some(old));
+    doc.set_attribute(kernel, "revision", "refs/tags/12.0.0");
+    assert_eq!(doc.as_str(), input.replacen(old, "refs/tags/12.0.0", 1));
+    doc.set_attribute(kernel, "revision", old);
+    assert_eq!(doc.as_str(), input);
+
+    doc.set_attribute(kernel, "groups", "kernel&<core>");
+    assert!(doc
+        .as_str()
+        .contains(r#"dest-branch="master" groups="kernel&amp;&lt;core&gt;"/>"#));
+    assert_eq!(
+        doc.attribute(kernel, "groups").as_deref(),
+        Some("kernel&<core>")
+    );
+    assert!(doc.remove_attribute(kernel, "groups"));
+    assert!(!doc.remove_attribute(kernel, "groups"));
+    assert_eq!(doc.as_str(), input);
+
+    let tools = doc.find("project", "name", "seL4_tools.git").unwrap();
+    doc.append_child(tools, r#"<linkfile src="a" dest="b"/>"#)
+        .unwrap();
+    assert!(doc.as_str().contains(
+        "    <linkfile src=\"cmake-tool/init-build.sh\" dest=\"init-build.sh\"/>\n    <linkfile src=\"a\" dest=\"b\"/>\n  </project>"
+    ));
+    let tools = doc.find("project", "name", "seL4_tools.git").unwrap();
+    let added = *doc.children(tools).last().unwrap();
+    doc.remove_element(added).unwrap();
+    assert_eq!(doc.as_str(), input);
+
+    let capdl = doc.find("project", "name", "capdl.git").unwrap();
+    doc.append_child(capdl, r#"<copyfile src="a" dest="b"/>"#)
+        .unwrap();
+    assert!(doc.as_str().contains(
+        "upstream=\"master\" dest-branch=\"master\">\n    <copyfile src=\"a\" dest=\"b\"/>\n  </project>\n  <project name=\"global-components.git\""
+    ));
+    let manifest = doc.manifest(path).unwrap();
+    assert_eq!(manifest.projects()[5].copyfiles().len(), 1);
+
+    let mut doc = Document::parse(input.clone()).unwrap();
+    let libzmq = doc.find("project", "name", "libzmq").unwrap();
+    doc.remove_element(libzmq).unwrap();
+    let expected: String = input
+        .lines()
+        .filter(|line| !line.contains("name=\"libzmq\""))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    assert_eq!(doc.as_str(), expected);
+    let musl = doc.find("project", "name", "musllibc.git").unwrap();
+    doc.insert_after(musl, r#"<project name="extra" path="extra"/>"#)
+        .unwrap();
+    assert!(doc.as_str().contains(
+        "upstream=\"sel4\"/>\n  <project name=\"extra\" path=\"extra\"/>\n  <project dest-branch=\"refs/tags/v1.7.0\""
+    ));
+    assert!(doc.remove_element(doc.root()).is_err());
+    assert!(Document::parse("<!-- empty -->".to_string()).is_err());
+}
+
+#[test]
+fn diff() {
+    let old = parse_file(Path::new("src/test_diff/old.xml")).unwrap();
+    let new = parse_file(Path::new("src/test_diff/new.xml")).unwrap();
+    assert_eq!(old.diff(&old), vec![]);
+    let changes = old.diff(&new);
+    let text: Vec<String> = changes.iter().map(Change::to_string).collect();
+    assert_snapshot!(text.join("\n"));
+    assert_debug_snapshot!(changes);
+}
+
+#[test]
+fn freeze() {
+    // Checkouts are created at runtime, git refuses to track nested `.git`s.
+    let workspace = std::env::temp_dir().join("git_repo_manifest_freeze");
+    let _ = fs::remove_dir_all(&workspace);
+    let write = |path: &str, contents: &str| {
+        let path = workspace.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    };
+    let kernel = "63432c91d54f9a1a1ab1ed278f788ba64ec6dba8";
+    let tools = "ffe3305d8d3926ccfa0fa8019063c786b75850d6";
+    let libs = "984ee48dec0310b367cf4a991da545c18ae6e1e8";
+    let pinned = "29b66b82fdae3a3791e18d9b6b7efaf2ad89096a";
+    // A detached HEAD, as left by `repo sync`.
+    write("kernel/.git/HEAD", &format!("{}\n", kernel));
+    // A branch with a loose ref.
+    write("tools/.git/HEAD", "ref: refs/heads/release\n");
+    write("tools/.git/refs/heads/release", &format!("{}\n", tools));
+    // A gitdir file pointing at a repository with packed refs.
+    write(
+        "lib/libs/.git",
+        "gitdir: ../../.repo/projects/lib/libs.git\n",
+    );
+    write(".repo/projects/lib/libs.git/HEAD", "ref: refs/heads/main\n");
+    write(
+        ".repo/projects/lib/libs.git/packed-refs",
+        &format!(
+            "# pack-refs with: peeled fully-peeled sorted\n{} refs/heads/main\n",
+            libs
+        ),
+    );
+    write("pinned/.git/HEAD", &format!("{}\n", pinned));
+
+    let mut manifest = parse_file(Path::new("src/test_freeze/default.xml")).unwrap();
+    manifest.freeze(&workspace).unwrap();
+    let frozen: Vec<_> = manifest
+        .projects()
+        .iter()
+        .map(|p| {
+            (
+                p.name().as_str(),
+                p.revision().as_deref().unwrap(),
+                p.upstream().as_deref(),
+                p.dest_branch().as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        frozen,
+        vec![
+            ("kernel", kernel, Some("stable"), Some("stable")),
+            ("tools", tools, Some("main"), Some("release")),
+            ("libs", libs, Some("main"), Some("main")),
+            ("pinned", pinned, None, None),
+        ]
+    );
+
+    fs::remove_file(workspace.join("pinned/.git/HEAD")).unwrap();
+    let mut manifest = parse_file(Path::new("src/test_freeze/default.xml")).unwrap();
+    match manifest.freeze(&workspace) {
+        Err(FreezeError::Io(path, _)) => assert_eq!(path, workspace.join("pinned")),
+        other => panic!("unexpected {:?}", other),
+    }
+    write("pinned/.git/HEAD", "ref: refs/heads/unborn\n");
+    let mut manifest = parse_file(Path::new("src/test_freeze/default.xml")).unwrap();
+    assert!(matches!(
+        manifest.freeze(&workspace),
+        Err(FreezeError::UnbornHead(_))
+    ));
+    // A cycle of symbolic refs is an error rather than endless recursion.
+    write("pinned/.git/refs/heads/unborn", "ref: refs/heads/cycle\n");
+    write("pinned/.git/refs/heads/cycle", "ref: refs/heads/unborn\n");
+    let mut manifest = parse_file(Path::new("src/test_freeze/default.xml")).unwrap();
+    match manifest.freeze(&workspace) {
+        Err(FreezeError::Io(path, err)) => {
+            assert_eq!(path, workspace.join("pinned"));
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    fs::remove_dir_all(&workspace).unwrap();
+}
+
+#[test]
+fn builders() {
+    let source = r#"<manifest>
+  <remote name="origin" fetch=".." review="https://review.example.com"/>
+  <default remote="origin" revision="main" sync-j="4"/>
+  <project name="kernel" path="src/kernel" groups="linux">
+    <linkfile src="Makefile" dest="Makefile"/>
+    <annotation name="owner" value="kernel-team"/>
+  </project>
+  <project name="tools"/>
+</manifest>"#;
+    let parsed = parse_str(source, Path::new("builders.xml")).unwrap();
+    let built = ManifestBuilder::default()
+        .remote(
+            RemoteBuilder::default()
+                .name("origin")
+                .fetch("..")
+                .review("https://review.example.com")
+                .build()
+                .unwrap(),
+        )
+        .default_tag(
+            DefaultTagBuilder::default()
+                .remote("origin")
+                .revision("main")
+                .sync_j(4u32)
+                .build()
+                .unwrap(),
+        )
+        .project(
+            ProjectBuilder::default()
+                .name("kernel")
+                .path("src/kernel")
+                .groups("linux")
+                .linkfile(
+                    LinkFileBuilder::default()
+                        .src("Makefile")
+                        .dest("Makefile")
+                        .build()
+                        .unwrap(),
+                )
+                .annotation(
+                    AnnotationBuilder::default()
+                        .name("owner")
+                        .value("kernel-team")
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        )
+        .project(ProjectBuilder::default().name("tools").build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(built, parsed);
+
+    let missing = RemoteBuilder::default().name("origin").build().unwrap_err();
+    assert_eq!(missing.to_string(), "`fetch` must be initialized");
+}
+
+#[test]
+fn edits() {
+    let mut manifest = parse_file(Path::new("src/test_inputs/camkes_vm_manifest.xml")).unwrap();
+    let project = |name: &str, path: &str| {
+        ProjectBuilder::default()
+            .name(name)
+            .path(path)
+            .build()
+            .unwrap()
+    };
+
+    assert_eq!(
+        manifest.add_project(project("other", "kernel")),
+        Err(EditError::PathInUse("kernel".into(), "seL4.git".into()))
+    );
+    let unknown_remote = ProjectBuilder::default()
+        .name("x")
+        .remote("nowhere")
+        .build()
+        .unwrap();
+    assert_eq!(
+        manifest.add_project(unknown_remote),
+        Err(EditError::UnknownRemote("nowhere".into()))
+    );
+    manifest
+        .add_project(project("sel4test.git", "projects/sel4test"))
+        .unwrap();
+
+    manifest
+        .set_project_revision("sel4test.git", "refs/tags/12.0.0")
+        .unwrap();
+    manifest
+        .move_project_to_remote("sel4test.git", "sel4proj")
+        .unwrap();
+    assert_eq!(
+        manifest.move_project_to_remote("sel4test.git", "nowhere"),
+        Err(EditError::UnknownRemote("nowhere".into()))
+    );
+    assert_eq!(
+        manifest.set_project_revision("missing", "main"),
+        Err(EditError::UnknownProject("missing".into()))
+    );
+    let added = manifest.projects().last().unwrap();
+    assert_eq!(added.revision().as_deref(), Some("refs/tags/12.0.0"));
+    assert_eq!(added.remote().as_deref(), Some("sel4proj"));
+
+    assert_eq!(
+        manifest.rename_remote("seL4", "sel4proj"),
+        Err(EditError::DuplicateRemote("sel4proj".into()))
+    );
+    manifest.rename_remote("sel4proj", "projects").unwrap();
+    assert_eq!(
+        manifest.remove_remote("projects").unwrap_err(),
+        EditError::RemoteInUse("projects".into())
+    );
+    manifest.rename_remote("seL4", "kernel").unwrap();
+    assert_eq!(
+        manifest.default().as_ref().unwrap().remote().as_deref(),
+        Some("kernel")
+    );
+    assert!(manifest.validate().is_empty());
+    let remotes: Vec<_> = manifest
+        .projects()
+        .iter()
+        .filter_map(|p| p.remote().as_deref())
+        .collect();
+    assert!(remotes.iter().all(|r| *r != "sel4proj"));
+    assert_eq!(remotes.iter().filter(|r| **r == "projects").count(), 8);
+
+    let removed = manifest.remove_project("sel4test.git").unwrap();
+    assert_eq!(removed.len(), 1);
+    assert_eq!(
+        manifest.remove_project("sel4test.git"),
+        Err(EditError::UnknownProject("sel4test.git".into()))
+    );
+    manifest.remove_project("libzmq").unwrap();
+    assert_eq!(manifest.remove_remote("zeromq").unwrap().name(), "zeromq");
+    assert!(manifest.validate().is_empty());
+}
+
+#[test]
+fn groups() {
+    let manifest = parse_file(Path::new("src/test_groups/default.xml")).unwrap();
+    assert_eq!(
+        manifest.projects()[0].group_set(),
+        vec![
+            "linux",
+            "core",
+            "all",
+            "name:kernel",
+            "path:src/kernel",
+            "default"
+        ]
+    );
+    assert_eq!(
+        manifest.projects()[1].group_set(),
+        vec!["notdefault", "docs", "all", "name:docs", "path:docs"]
+    );
+
+    let selected = |filter: &str| -> Vec<&str> {
+        let filter: GroupFilter = filter.parse().unwrap();
+        manifest
+            .projects_in_groups(&filter)
+            .into_iter()
+            .map(|p| p.name().as_str())
+            .collect()
+    };
+    assert_eq!(selected("default"), vec!["kernel", "tools"]);
+    assert_eq!(
+        selected("default,platform-linux"),
+        vec!["kernel", "linux-sdk", "tools"]
+    );
+    assert_eq!(
+        selected("all"),
+        vec!["kernel", "docs", "darwin-sdk", "linux-sdk", "tools"]
+    );
+    assert_eq!(selected("all,-notdefault"), vec!["kernel", "tools"]);
+    assert_eq!(selected("default,-core"), vec!["tools"]);
+    assert_eq!(selected("-core,default"), vec!["kernel", "tools"]);
+    assert_eq!(selected("docs name:tools"), vec!["docs", "tools"]);
+    assert_eq!(selected("path:src/kernel"), vec!["kernel"]);
+    assert_eq!("".parse::<GroupFilter>().unwrap(), GroupFilter::default());
+}
+
+#[test]
+fn superproject() {
+    let path = Path::new("src/test_inputs/superproject.xml");
+    let mut manifest = parse_file(path).unwrap();
+    let superproject = manifest.superproject().as_ref().unwrap();
+    assert_eq!(superproject.name(), "platform/superproject");
+    assert_eq!(superproject.revision(), &None);
+    assert_eq!(
+        manifest.contactinfo().as_ref().unwrap().bugurl(),
+        "https://issuetracker.google.com/issues/new?component=1"
+    );
+    assert_eq!(manifest.superproject_remote().unwrap().name(), "aosp");
+    assert_eq!(
+        manifest
+            .superproject_url(Some("https://android.googlesource.com/platform/manifest"))
+            .as_deref(),
+        Some("https://android.googlesource.com/platform/superproject")
+    );
+    manifest.set_defaults();
+    let superproject = manifest.superproject().as_ref().unwrap();
+    assert_eq!(superproject.revision().as_deref(), Some("main"));
+    assert!(manifest.validate().is_empty());
+
+    let source = fs::read_to_string(path).unwrap();
+    let broken = source.replace(r#"remote="aosp"/>"#, r#"remote="gone"/>"#);
+    let manifest = parse_str(&broken, path).unwrap();
+    assert_eq!(
+        manifest.validate(),
+        vec![ValidationError::UnknownSuperprojectRemote("gone".into())]
+    );
+
+    let mut manifest = parse_str(&source, path).unwrap();
+    let other = parse_str(
+        r#"<manifest><superproject name="other/superproject"/></manifest>"#,
+        Path::new("other.xml"),
+    )
+    .unwrap();
+    assert_eq!(
+        manifest
+            .merge(other, Path::new("other.xml"))
+            .unwrap_err()
+            .to_string(),
+        "other.xml: duplicate <superproject> conflicts with an earlier definition"
+    );
+
+    manifest.rename_remote("aosp", "google").unwrap();
+    let superproject = manifest.superproject().as_ref().unwrap();
+    assert_eq!(superproject.remote().as_deref(), Some("google"));
+    assert!(manifest.validate().is_empty());
+    let mut manifest = parse_str(
+        r#"<manifest>
+  <remote name="origin" fetch=".."/>
+  <remote name="super" fetch=".."/>
+  <default remote="origin"/>
+  <superproject name="platform/superproject" remote="super"/>
+</manifest>"#,
+        path,
+    )
+    .unwrap();
+    assert_eq!(
+        manifest.remove_remote("super"),
+        Err(EditError::RemoteInUse("super".into()))
+    );
+}
+
+#[test]
+fn submanifests() {
+    let repo_dir = Path::new("src/test_submanifests/.repo");
+    let manifest_dir = repo_dir.join("manifests");
+    let manifest = load(&manifest_dir, &manifest_dir.join("default.xml")).unwrap();
+    let submanifest = &manifest.submanifests()[0];
+    assert_eq!(submanifest.relpath(), "vendor/acme");
+    assert_eq!(submanifest.project().as_deref(), Some("acme/manifest"));
+
+    let loaded = load_submanifests(repo_dir, &manifest).unwrap();
+    let paths: Vec<_> = loaded.iter().map(|sub| sub.path().as_str()).collect();
+    assert_eq!(paths, vec!["vendor/acme", "vendor/acme/deep"]);
+    assert_eq!(
+        loaded[1].repo_dir(),
+        &repo_dir.join("submanifests/vendor/acme/submanifests/deep")
+    );
+    let projects: Vec<_> = loaded
+        .iter()
+        .flat_map(|sub| sub.manifest().projects())
+        .map(|p| (p.name().as_str(), p.relpath(), p.groups().as_deref()))
+        .collect();
+    assert_eq!(
+        projects,
+        vec![
+            ("drivers", "vendor/acme/drivers", Some("hw,vendor")),
+            ("firmware", "vendor/acme/deep/fw", None),
+        ]
+    );
+    let drivers = &loaded[0].manifest().projects()[0];
+    assert_eq!(drivers.linkfiles()[0].dest(), "vendor/acme/Makefile");
+
+    let missing = parse_str(
+        r#"<manifest><submanifest name="gone"/></manifest>"#,
+        Path::new("missing.xml"),
+    )
+    .unwrap();
+    match load_submanifests(repo_dir, &missing) {
+        Err(LoadError::Submanifest(name, err)) => {
+            assert_eq!(name, "gone");
+            assert!(matches!(*err, LoadError::Io(..)));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let mut manifest = parse_str(
+        r#"<manifest>
+  <remote name="origin" fetch=".."/>
+  <remote name="vendor" fetch="https://vendor.example.com"/>
+  <default remote="origin"/>
+  <submanifest name="acme" remote="vendor" project="acme/manifest"/>
+</manifest>"#,
+        Path::new("default.xml"),
+    )
+    .unwrap();
+    assert_eq!(
+        manifest.remove_remote("vendor"),
+        Err(EditError::RemoteInUse("vendor".into()))
+    );
+    manifest.rename_remote("vendor", "acme").unwrap();
+    assert_eq!(manifest.submanifests()[0].remote().as_deref(), Some("acme"));
+}
+
+#[test]
+fn unknowns() {
+    let path = Path::new("manifest.xml");
+    let source = r#"<manifest>
+  <remote name="origin" fetch="https://example.com/"/>
+  <project name="kernel" revison="main" x-owner="kernel-team">
+    <annotation name="a" value="b"/>
+    <vendor-hint level="2">keep me</vendor-hint>
+  </project>
+  <proejct name="tools"/>
+</manifest>"#;
+    let manifest = parse_str(source, path).unwrap();
+    let unknowns: Vec<_> = manifest.unknowns().iter().map(|u| u.to_string()).collect();
+    assert_eq!(
+        unknowns,
+        vec![
+            "manifest.xml:3:26: unknown attribute `revison` of <project>",
+            "manifest.xml:3:41: unknown attribute `x-owner` of <project>",
+            "manifest.xml:5:6: unknown element <vendor-hint> in <project>",
+            "manifest.xml:7:4: unknown element <proejct> in <manifest>",
+        ]
+    );
+    let project = &manifest.projects()[0];
+    assert_eq!(project.revision(), &None);
+    assert_eq!(project.extra().attribute("x-owner"), Some("kernel-team"));
+    assert_eq!(
+        project.extra().elements().collect::<Vec<_>>(),
+        vec![r#"<vendor-hint level="2">keep me</vendor-hint>"#]
+    );
+
+    let output = manifest.to_xml().unwrap();
+    assert!(output.contains(r#"revison="main""#));
+    assert!(output.contains(r#"<vendor-hint level="2">keep me</vendor-hint>"#));
+    assert!(output.contains(r#"<proejct name="tools"/>"#));
+    let reparsed = parse_str(&output, path).unwrap();
+    assert_eq!(reparsed, manifest);
+
+    let known = parse_str(r#"<manifest><project name="a"/></manifest>"#, path).unwrap();
+    assert!(known.unknowns().is_empty());
+}
+
+#[test]
+fn revisions() {
+    let parse = |revision: &str| revision.parse::<Revision>().unwrap();
+    let id = "d062edd8c142384792955796329baf1e5a3377cd";
+    assert_eq!(parse(id), Revision::CommitId(id.into()));
+    assert_eq!(parse("master"), Revision::Branch("master".into()));
+    assert_eq!(
+        parse("refs/heads/master"),
+        Revision::Branch("master".into())
+    );
+    assert_eq!(parse("refs/tags/v4.2.5"), Revision::Tag("v4.2.5".into()));
+    assert_eq!(
+        parse("refs/changes/45/1245/3"),
+        Revision::Ref("refs/changes/45/1245/3".into())
+    );
+    // An abbreviated id cannot be told from a branch name.
+    assert_eq!(parse("d062edd"), Revision::Branch("d062edd".into()));
+    assert_eq!(parse("sel4").to_ref(), "refs/heads/sel4");
+
+    let manifest = parse_file(Path::new("src/test_inputs/camkes_vm_manifest.xml")).unwrap();
+    let libzmq = manifest
+        .projects()
+        .iter()
+        .find(|p| p.name() == "libzmq")
+        .unwrap();
+    let kinds = [
+        libzmq.typed_revision().unwrap(),
+        libzmq.typed_upstream().unwrap(),
+        libzmq.typed_dest_branch().unwrap(),
+    ];
+    assert_eq!(
+        kinds.iter().map(Revision::kind).collect::<Vec<_>>(),
+        vec!["commit", "tag", "tag"]
+    );
+    assert!(kinds.iter().all(Revision::is_pinned));
+    assert!(!parse("master").is_pinned());
+}
+
+#[test]
+fn flatten() {
+    let dir = Path::new("src/test_includes");
+    let mut manifest = load(dir, &dir.join("default.xml")).unwrap();
+    manifest.set_defaults();
+    let output = manifest.to_xml().unwrap();
+    assert!(!output.contains("<include"));
+    assert!(output.contains("<notice>You checked out the default manifest</notice>"));
+    assert!(output.contains(r#"enabled-list="post-sync pre-upload""#));
+    let flattened = parse_str(&output, Path::new("flattened.xml")).unwrap();
+    assert_eq!(flattened, manifest);
+    // `--fmt --check` accepts the output as written.
+    assert_eq!(format_manifest(&output, false).unwrap(), output);
+}
+
+#[test]
+fn split() {
+    let path = Path::new("src/test_inputs/camkes_vm_manifest.xml");
+    let manifest = parse_file(path).unwrap();
+    let mut names: Vec<_> = manifest
+        .projects()
+        .iter()
+        .map(|p| p.name().clone())
+        .collect();
+    let (mut top, parts) = manifest.split(SplitBy::Remote, "remotes");
+    assert!(top.projects().is_empty());
+    let xml = top.to_xml().unwrap();
+    assert_eq!(format_manifest(&xml, false).unwrap(), xml);
+    assert_eq!(top.remotes().len(), 5);
+    let includes: Vec<_> = top.includes().iter().map(|i| i.name().as_str()).collect();
+    assert_eq!(
+        includes,
+        vec![
+            "remotes/sel4proj.xml",
+            "remotes/seL4.xml",
+            "remotes/zeromq.xml",
+            "remotes/picotcp.xml",
+            "remotes/polly.xml",
+        ]
+    );
+    // Projects without a remote attribute go with the default remote.
+    let sel4 = &parts[1].1;
+    assert!(sel4.projects().iter().all(|p| p.remote().is_none()));
+    for (name, part) in parts {
+        assert!(part.remotes().is_empty());
+        let xml = part.to_xml().unwrap();
+        assert_eq!(format_manifest(&xml, false).unwrap(), xml);
+        top.merge(parse_str(&xml, Path::new(&name)).unwrap(), Path::new(&name))
+            .unwrap();
+    }
+    let mut merged: Vec<_> = top.projects().iter().map(|p| p.name().clone()).collect();
+    names.sort();
+    merged.sort();
+    assert_eq!(merged, names);
+
+    let manifest = parse_str(
+        r#"<manifest>
+  <project name="a" groups="platform/linux,tools"/>
+  <project name="b"/>
+  <project name="c" groups="platform/linux"/>
+</manifest>"#,
+        path,
+    )
+    .unwrap();
+    let (_, parts) = manifest.split(SplitBy::Group, "groups");
+    let parts: Vec<_> = parts
+        .iter()
+        .map(|(name, part)| (name.as_str(), part.projects().len()))
+        .collect();
+    assert_eq!(
+        parts,
+        vec![
+            ("groups/platform_linux.xml", 2),
+            ("groups/ungrouped.xml", 1)
+        ]
+    );
+
+    // Distinct groups never share a file, whatever their names.
+    let manifest = parse_str(
+        r#"<manifest>
+  <project name="a" groups="platform/linux"/>
+  <project name="b" groups="platform_linux"/>
+  <project name="c" groups="ungrouped"/>
+  <project name="d"/>
+</manifest>"#,
+        path,
+    )
+    .unwrap();
+    let (_, parts) = manifest.split(SplitBy::Group, "groups");
+    let parts: Vec<_> = parts
+        .iter()
+        .map(|(name, part)| (name.as_str(), part.projects()[0].name().as_str()))
+        .collect();
+    assert_eq!(
+        parts,
+        vec![
+            ("groups/platform_linux.xml", "a"),
+            ("groups/platform_linux-2.xml", "b"),
+            ("groups/ungrouped.xml", "c"),
+            ("groups/ungrouped-2.xml", "d"),
+        ]
+    );
+}
+
+#[test]
+fn format() {
+    let source = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- Keep this comment. -->
+<manifest>
+	<remote fetch='https://example.com/' name="origin" type="AGit"/>
+
+
+	<default revision="main" remote="origin"/>
+	<!-- Tools -->
+	<project path="tools" name="tools" x-owner="infra"><linkfile dest="b" src="a"/></project>
+	<!-- The kernel -->
+	<project revision="v1" path="kernel" name="kernel"/>
+</manifest>
+"#;
+    let formatted = format_manifest(source, false).unwrap();
+    assert_snapshot!(formatted, @r###"
+    <?xml version="1.0" encoding="UTF-8"?>
+    <!-- Keep this comment. -->
+    <manifest>
+      <remote name="origin" fetch="https://example.com/" type="agit"/>
+
+      <default remote="origin" revision="main"/>
+      <!-- Tools -->
+      <project name="tools" path="tools" x-owner="infra">
+        <linkfile src="a" dest="b"/>
+      </project>
+      <!-- The kernel -->
+      <project name="kernel" path="kernel" revision="v1"/>
+    </manifest>
+    "###);
+    assert_eq!(format_manifest(&formatted, false).unwrap(), formatted);
+
+    let sorted = format_manifest(source, true).unwrap();
+    let kernel = sorted.find("<!-- The kernel -->").unwrap();
+    let tools = sorted.find("<!-- Tools -->").unwrap();
+    assert!(kernel < tools);
+    assert!(sorted.find(r#"<project name="kernel""#).unwrap() < tools);
+    assert!(format_manifest("<manifest><project></manifest>", false).is_err());
+}
+
+#[test]
+fn round_trip_test_inputs() {
+    glob!("test_inputs/*.xml", |path| {
+        let manifest = parse_file(path).unwrap();
+        let output = manifest.to_xml().unwrap();
+        assert_eq!(parse_str(&output, path).unwrap(), manifest);
+        assert_eq!(format_manifest(&output, false).unwrap(), output);
+    });
+}
+
+#[test]
+fn json_schema() {
+    let schema: Value = serde_json::from_str(JSON_SCHEMA).unwrap();
+    let validator = jsonschema::validator_for(&schema).unwrap();
+    let check = |value: &Value| {
+        let errors: Vec<_> = validator
+            .iter_errors(value)
+            .map(|e| format!("{}: {}", e.instance_path, e))
+            .collect();
+        assert!(errors.is_empty(), "{}", errors.join("\n"));
+    };
+    glob!("test_inputs/*.xml", |path| {
+        let mut manifest = parse_file(path).unwrap();
+        manifest.set_defaults();
+        check(&serde_json::to_value(&manifest).unwrap());
+    });
+    let dir = Path::new("src/test_includes");
+    let manifest = load(dir, &dir.join("default.xml")).unwrap();
+    let value = serde_json::to_value(&manifest).unwrap();
+    assert_eq!(value["notice"], "You checked out the default manifest");
+    assert_eq!(value["repo-hooks"]["enabled-list"], "post-sync pre-upload");
+    check(&value);
+    let mut renamed = value.clone();
+    renamed["projects"] = renamed["project"].take();
+    assert!(!validator.is_valid(&renamed));
+    let mut listed = value.clone();
+    listed["repo-hooks"]["enabled-list"] = "post-sync,pre-upload".into();
+    assert!(!validator.is_valid(&listed));
+}
+
+#[test]
+fn gitmodules() {
+    let path = Path::new("src/test_inputs/camkes_vm_manifest.xml");
+    let mut manifest = parse_file(path).unwrap();
+    manifest.set_defaults();
+    let manifest_url = "https://github.com/seL4/camkes-vm-manifest.git";
+    let submodules = manifest.submodules(Some(manifest_url)).unwrap();
+    assert_eq!(submodules.len(), manifest.projects().len());
+    let gitmodules = write_gitmodules(&submodules);
+    assert_snapshot!(gitmodules);
+    // libzmq is pinned to a commit and follows a tag, not a branch.
+    let libzmq = submodules
+        .iter()
+        .find(|s| s.path() == "projects/libzmq")
+        .unwrap();
+    assert_eq!(libzmq.branch(), &None);
+    let script = pin_script(&submodules);
+    assert!(script.contains(
+        "git update-index --add --cacheinfo \
+         160000,d062edd8c142384792955796329baf1e5a3377cd,'projects/libzmq'"
+    ));
+
+    let mut parsed = parse_gitmodules(&gitmodules).unwrap();
+    assert!(parsed.iter().all(|s| s.revision().is_none()));
+    let ls_tree: String = submodules
+        .iter()
+        .filter_map(|s| {
+            Some(format!(
+                "160000 commit {}\t{}\n",
+                s.revision().as_ref()?,
+                s.path()
+            ))
+        })
+        .collect();
+    apply_gitlinks(&mut parsed, &ls_tree);
+    assert_eq!(parsed, submodules);
+
+    let mut imported = Manifest::from_submodules(&parsed);
+    assert_eq!(imported.remotes().len(), 1);
+    imported.set_defaults();
+    assert_eq!(imported.submodules(None).unwrap(), submodules);
+
+    // The fetch URLs of camkes-vm-manifest are relative to the manifest URL.
+    assert_eq!(
+        manifest.submodules(None),
+        Err(GitmodulesError::RelativeUrl(
+            "camkes-vm-apps.git".into(),
+            "../sel4proj/camkes-vm-apps.git".into()
+        ))
+    );
+
+    let quoted = Submodule::new(
+        "it's".into(),
+        "it's".into(),
+        "https://example.com/quoted".into(),
+        None,
+        Some("63432c91d54f9a1a1ab1ed278f788ba64ec6dba8".into()),
+    );
+    assert!(pin_script(&[quoted])
+        .ends_with("160000,63432c91d54f9a1a1ab1ed278f788ba64ec6dba8,'it'\\''s'\n"));
+
+    let special = Submodule::new(
+        r#"a "quoted\" name"#.into(),
+        "a \"b\\c\"; d # e\nf\t ".into(),
+        "https://example.com/special;#".into(),
+        Some(" main ".into()),
+        None,
+    );
+    let gitmodules = write_gitmodules(std::slice::from_ref(&special));
+    assert_snapshot!(gitmodules, @r###"
+    [submodule "a \"quoted\\\" name"]
+    	path = "a \"b\\c\"; d # e\nf\t "
+    	url = "https://example.com/special;#"
+    	branch = " main "
+    "###);
+    assert_eq!(parse_gitmodules(&gitmodules).unwrap(), vec![special]);
+
+    let written_by_git = "[submodule \"x\"] ; comment
+\tpath = lib/\"x y\"  # comment
+\tURL = https://example.com/x\t
+\tbranch = \"main\"
+";
+    let parsed = parse_gitmodules(written_by_git).unwrap();
+    assert_eq!(parsed[0].path(), "lib/x y");
+    assert_eq!(parsed[0].url(), "https://example.com/x");
+    assert_eq!(parsed[0].branch().as_deref(), Some("main"));
+    assert_eq!(
+        parse_gitmodules("[submodule \"x\"]\n\tpath = \"x\n"),
+        Err(GitmodulesError::Syntax(2))
+    );
+}
@@ -0,0 +1,186 @@
+use crate::{Manifest, Project, Remote};
+
+pub(crate) fn has_scheme(url: &str) -> bool {
+    match url.find(':') {
+        Some(colon) if colon > 0 => {
+            let scheme = &url[..colon];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+/// Whether `url` is an scp-like `[user@]host:path`, which git treats as ssh.
+fn is_scp_like(url: &str) -> bool {
+    match (url.find(':'), url.find('/')) {
+        (Some(colon), Some(slash)) => colon < slash && !url[colon..].starts_with("://"),
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+/// Whether `url` can be cloned from as is, rather than relative to another.
+pub(crate) fn is_absolute(url: &str) -> bool {
+    has_scheme(url) || is_scp_like(url) || url.starts_with('/')
+}
+
+/// Removes `.` and `..` segments as described by RFC 3986 section 5.2.4.
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    let segments: Vec<&str> = path.split('/').collect();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match *segment {
+            "." => {
+                if last {
+                    output.push("");
+                }
+            }
+            ".." => {
+                // Never remove the empty segment before an absolute path's root.
+                if output != [""] {
+                    output.pop();
+                }
+                if last {
+                    output.push("");
+                }
+            }
+            segment => output.push(segment),
+        }
+    }
+    let joined = output.join("/");
+    if path.starts_with('/') && !joined.starts_with('/') {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+/// Splits `url` into the `scheme://authority` prefix and the path.
+pub(crate) fn split_authority(url: &str) -> (&str, &str) {
+    match url.find("://") {
+        Some(scheme_end) => {
+            let authority_start = scheme_end + 3;
+            match url[authority_start..].find('/') {
+                Some(slash) => url.split_at(authority_start + slash),
+                None => (url, ""),
+            }
+        }
+        None => ("", url),
+    }
+}
+
+/// Resolves a remote's `fetch` attribute against the URL the manifest
+/// repository was cloned from, the way repo does.
+///
+/// Absolute and scp-like fetch URLs are returned unchanged, while relative ones
+/// such as `../seL4` are joined to `manifest_url` like a relative link,
+/// `https://github.com/seL4/camkes-vm-manifest.git` and `../seL4` yield
+/// `https://github.com/seL4`. The result has no trailing slash.
+pub fn resolve_url(manifest_url: &str, fetch: &str) -> String {
+    let fetch = fetch.trim_end_matches('/');
+    let manifest_url = manifest_url.trim_end_matches('/');
+    if has_scheme(fetch) || is_scp_like(fetch) {
+        return fetch.to_string();
+    }
+
+    // Like repo, give an scp-like manifest URL a placeholder scheme so that it
+    // has an authority, and strip it again afterwards.
+    let placeholder = "gopher://";
+    let base = if is_scp_like(manifest_url) {
+        format!("{}{}", placeholder, manifest_url)
+    } else {
+        manifest_url.to_string()
+    };
+    let (authority, base_path) = split_authority(&base);
+    let resolved = if fetch.is_empty() {
+        base.clone()
+    } else if let Some(network_path) = fetch.strip_prefix("//") {
+        match base.find("://") {
+            Some(scheme_end) => format!("{}//{}", &base[..=scheme_end], network_path),
+            None => fetch.to_string(),
+        }
+    } else if fetch.starts_with('/') {
+        format!("{}{}", authority, remove_dot_segments(fetch))
+    } else {
+        let directory = match base_path.rfind('/') {
+            Some(slash) => &base_path[..=slash],
+            None if authority.is_empty() => "",
+            None => "/",
+        };
+        let merged = format!("{}{}", directory, fetch);
+        format!("{}{}", authority, remove_dot_segments(&merged))
+    };
+    let resolved = match resolved.strip_prefix(placeholder) {
+        Some(stripped) if base.starts_with(placeholder) => stripped,
+        _ => &resolved,
+    };
+    match resolved.trim_end_matches('/') {
+        "" => resolved.to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+impl Remote {
+    /// The absolute fetch URL, `fetch` is used verbatim without a `manifest_url`.
+    pub fn resolved_fetch(&self, manifest_url: Option<&str>) -> String {
+        match manifest_url {
+            Some(manifest_url) => resolve_url(manifest_url, &self.fetch),
+            None => self.fetch.clone(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Replaces every remote's relative `fetch` with an absolute URL.
+    pub fn resolve_fetch_urls(&mut self, manifest_url: &str) {
+        for remote in &mut self.remotes {
+            remote.fetch = resolve_url(manifest_url, &remote.fetch);
+        }
+    }
+
+    /// The URL repo clones `project` from, its remote's fetch URL followed by
+    /// the project name, or `None` if the remote is unknown.
+    pub fn clone_url(&self, project: &Project, manifest_url: Option<&str>) -> Option<String> {
+        self.repository_url(&project.name, project.remote.as_ref(), manifest_url)
+    }
+
+    /// The remote of the `<superproject>`, its own or the default one.
+    pub fn superproject_remote(&self) -> Option<&Remote> {
+        let remote_name = self
+            .superproject
+            .as_ref()?
+            .remote
+            .as_ref()
+            .or_else(|| self.default.as_ref()?.remote.as_ref())?;
+        self.remotes.iter().find(|r| r.name == *remote_name)
+    }
+
+    /// The URL of the `<superproject>`, like `clone_url`.
+    pub fn superproject_url(&self, manifest_url: Option<&str>) -> Option<String> {
+        let superproject = self.superproject.as_ref()?;
+        self.repository_url(
+            &superproject.name,
+            superproject.remote.as_ref(),
+            manifest_url,
+        )
+    }
+
+    fn repository_url(
+        &self,
+        name: &str,
+        remote: Option<&String>,
+        manifest_url: Option<&str>,
+    ) -> Option<String> {
+        let remote_name = remote.or_else(|| self.default.as_ref()?.remote.as_ref())?;
+        let remote = self.remotes.iter().find(|r| r.name == *remote_name)?;
+        Some(format!(
+            "{}/{}",
+            remote.resolved_fetch(manifest_url).trim_end_matches('/'),
+            name
+        ))
+    }
+}
@@ -0,0 +1,136 @@
+use crate::Manifest;
+use quick_error::quick_error;
+use std::collections::{HashMap, HashSet};
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum ValidationError {
+        UnknownRemote(project: String, remote: String) {
+            display("project {} references unknown remote {}", project, remote)
+        }
+        NoRemote(project: String) {
+            display("project {} has no remote and there is no default remote", project)
+        }
+        UnknownDefaultRemote(remote: String) {
+            display("<default> references unknown remote {}", remote)
+        }
+        DuplicateProject(project: String, path: String) {
+            display("duplicate project {} at path {}", project, path)
+        }
+        DuplicatePath(path: String, first: String, second: String) {
+            display("projects {} and {} are both checked out at {}", first, second, path)
+        }
+        NestedPath(outer: String, inner: String) {
+            display("checkout path {} is nested within {}", inner, outer)
+        }
+        DuplicateRemote(remote: String) {
+            display("duplicate remote {}", remote)
+        }
+        UnknownHooksProject(project: String) {
+            display("<repo-hooks> in-project references unknown project {}", project)
+        }
+        InvalidCloneDepth(project: String, depth: u32) {
+            display("project {} has invalid clone-depth {}, expected a positive integer", project, depth)
+        }
+        UnknownSuperprojectRemote(remote: String) {
+            display("<superproject> references unknown remote {}", remote)
+        }
+        NoSuperprojectRemote {
+            display("<superproject> has no remote and there is no default remote")
+        }
+    }
+}
+
+impl ValidationError {
+    /// Whether repo would refuse the manifest, as it does for dangling
+    /// references and duplicates. Nested checkout paths are only worth a
+    /// warning.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, ValidationError::NestedPath(..))
+    }
+}
+
+impl Manifest {
+    /// Checks the references between elements, returning every problem found.
+    ///
+    /// This is meant to be run after includes, local manifests and
+    /// `<remove-project>`s have been resolved, an empty result means the
+    /// manifest is consistent.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        use ValidationError as E;
+        let mut errors = Vec::new();
+
+        let mut remotes = HashSet::new();
+        for remote in &self.remotes {
+            if !remotes.insert(remote.name.as_str()) {
+                errors.push(E::DuplicateRemote(remote.name.clone()));
+            }
+        }
+
+        let default_remote = self.default.as_ref().and_then(|d| d.remote.as_ref());
+        if let Some(remote) = default_remote {
+            if !remotes.contains(remote.as_str()) {
+                errors.push(E::UnknownDefaultRemote(remote.clone()));
+            }
+        }
+
+        let mut paths: HashMap<&str, &str> = HashMap::new();
+        for project in &self.projects {
+            match &project.remote {
+                Some(remote) if !remotes.contains(remote.as_str()) => {
+                    errors.push(E::UnknownRemote(project.name.clone(), remote.clone()))
+                }
+                None if default_remote.is_none() => errors.push(E::NoRemote(project.name.clone())),
+                _ => (),
+            }
+
+            let path = project.relpath();
+            match paths.get(path) {
+                Some(name) if *name == project.name => {
+                    errors.push(E::DuplicateProject(project.name.clone(), path.to_string()))
+                }
+                Some(name) => errors.push(E::DuplicatePath(
+                    path.to_string(),
+                    name.to_string(),
+                    project.name.clone(),
+                )),
+                None => {
+                    paths.insert(path, &project.name);
+                }
+            }
+
+            if project.clone_depth == Some(0) {
+                errors.push(E::InvalidCloneDepth(project.name.clone(), 0));
+            }
+        }
+
+        let mut sorted: Vec<&str> = paths.keys().copied().collect();
+        sorted.sort_unstable();
+        for (i, outer) in sorted.iter().enumerate() {
+            // Paths sharing `outer` as a string prefix sort contiguously after it.
+            let prefix = format!("{}/", outer.trim_end_matches('/'));
+            for inner in sorted[i + 1..].iter().take_while(|p| p.starts_with(outer)) {
+                if inner.starts_with(&prefix) {
+                    errors.push(E::NestedPath(outer.to_string(), inner.to_string()));
+                }
+            }
+        }
+
+        if let Some(superproject) = &self.superproject {
+            match &superproject.remote {
+                Some(remote) if !remotes.contains(remote.as_str()) => {
+                    errors.push(E::UnknownSuperprojectRemote(remote.clone()))
+                }
+                None if default_remote.is_none() => errors.push(E::NoSuperprojectRemote),
+                _ => (),
+            }
+        }
+
+        if let Some(hooks) = &self.repo_hooks {
+            if !self.projects.iter().any(|p| p.name == hooks.in_project) {
+                errors.push(E::UnknownHooksProject(hooks.in_project.clone()));
+            }
+        }
+        errors
+    }
+}
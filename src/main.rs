@@ -1,4 +1,3 @@
-use clap;
 use dirs_next as dirs;
 use envsubst::{self, substitute};
 use git_repo_manifest as manifest;
@@ -8,21 +7,71 @@ use quick_xml as qxml;
 use serde::ser::Serialize;
 
 use std::collections::HashMap;
-use std::io::{BufRead, Read};
+use std::io::{BufRead, Read, Write};
 use std::str::FromStr;
-use std::{env, ffi, fs, io, path, str};
+use std::{env, ffi, fs, io, path, process, str};
 
 quick_error! {
     #[derive(Debug)]
     enum Error {
         Io(err: io::Error) {
             from()
+            display("{}", err)
         }
         Deserialization(err: manifest::de::DeError) {
             from()
+            display("{}", err)
+        }
+        Parse(err: manifest::ParseError) {
+            from()
+            display("{}", err)
         }
         Envsubst(err: envsubst::Error) {
             from()
+            display("{}", err)
+        }
+        Load(err: manifest::LoadError) {
+            from()
+            display("{}", err)
+        }
+        Freeze(err: manifest::FreezeError) {
+            from()
+            display("{}", err)
+        }
+        Json(err: serde_json::Error) {
+            from()
+            display("{}", err)
+        }
+        Invalid(errors: Vec<manifest::ValidationError>) {
+            display("{}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))
+        }
+        Yaml(err: serde_yaml_ng::Error) {
+            from()
+            display("{}", err)
+        }
+        Toml(err: toml::ser::Error) {
+            from()
+            display("{}", err)
+        }
+        Gitmodules(err: manifest::GitmodulesError) {
+            from()
+            display("{}", err)
+        }
+        Document(err: manifest::DocumentError) {
+            from()
+            display("{}", err)
+        }
+        Unformatted(paths: Vec<path::PathBuf>) {
+            display("{}", paths.iter().map(|p| format!("{} is not formatted", p.display())).collect::<Vec<_>>().join("\n"))
+        }
+        File(path: path::PathBuf, err: Box<Error>) {
+            display("{}: {}", path.display(), err)
+        }
+        Multiple(errors: Vec<Error>) {
+            display("{}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))
+        }
+        Strict(unknowns: Vec<manifest::Unknown>) {
+            display("{}", unknowns.iter().map(|u| u.to_string()).collect::<Vec<_>>().join("\n"))
         }
 
         FileNotFound(p: Box<path::PathBuf>) {
@@ -61,16 +110,17 @@ fn envsubst_write(
     output: &mut dyn io::Write,
     contents: &HashMap<String, String>,
 ) -> Result<(), Error> {
-    let s = substitute(template_string, &contents)?;
+    let s = substitute(template_string, contents)?;
     Ok(output.write_all(s.as_bytes())?)
 }
 
+#[allow(clippy::wrong_self_convention)]
 trait IntoHash<K, V> {
     fn into_hash(&self, context: &mut HashMap<K, V>);
 }
 
 impl IntoHash<String, String> for manifest::Remote {
-    fn into_hash<'a>(&self, context: &'a mut HashMap<String, String>) {
+    fn into_hash(&self, context: &mut HashMap<String, String>) {
         context.insert("remote_name".to_string(), self.name().to_string());
         let () = self
             .pushurl()
@@ -87,6 +137,76 @@ impl IntoHash<String, String> for manifest::Remote {
                 let _ = context.insert("review_url".to_string(), review.to_string());
             })
             .collect();
+        self.annotations().into_hash(context);
+    }
+}
+
+impl IntoHash<String, String> for Vec<manifest::Annotation> {
+    fn into_hash(&self, context: &mut HashMap<String, String>) {
+        for annotation in self {
+            context.insert(
+                format!("annotation_{}", annotation.name()),
+                annotation.value().to_string(),
+            );
+        }
+    }
+}
+
+impl IntoHash<String, String> for manifest::Project {
+    fn into_hash(&self, context: &mut HashMap<String, String>) {
+        context.insert("project_name".to_string(), self.name().to_string());
+        context.insert("project_path".to_string(), self.relpath().to_string());
+        let attributes = [
+            ("revision", self.revision()),
+            ("upstream", self.upstream()),
+            ("dest_branch", self.dest_branch()),
+        ];
+        for (key, value) in attributes.iter() {
+            if let Some(value) = value {
+                context.insert(key.to_string(), value.to_string());
+            }
+        }
+        if let Some(revision) = self.typed_revision() {
+            context.insert("revision_kind".to_string(), revision.kind().to_string());
+            context.insert("revision_ref".to_string(), revision.to_ref());
+        }
+        // Space separated `src:dest` pairs.
+        let linkfiles: Vec<String> = self
+            .linkfiles()
+            .iter()
+            .map(|link| format!("{}:{}", link.src(), link.dest()))
+            .collect();
+        context.insert("linkfiles".to_string(), linkfiles.join(" "));
+        let copyfiles: Vec<String> = self
+            .copyfiles()
+            .iter()
+            .map(|copy| format!("{}:{}", copy.src(), copy.dest()))
+            .collect();
+        context.insert("copyfiles".to_string(), copyfiles.join(" "));
+        self.annotations().into_hash(context);
+    }
+}
+
+impl IntoHash<String, String> for Manifest {
+    fn into_hash(&self, context: &mut HashMap<String, String>) {
+        if let Some(superproject) = self.superproject() {
+            context.insert(
+                "superproject_name".to_string(),
+                superproject.name().to_string(),
+            );
+            if let Some(remote) = self.superproject_remote() {
+                context.insert("superproject_remote".to_string(), remote.name().to_string());
+            }
+            if let Some(revision) = superproject.revision() {
+                context.insert("superproject_revision".to_string(), revision.to_string());
+            }
+            if let Some(url) = self.superproject_url(None) {
+                context.insert("superproject_url".to_string(), url);
+            }
+        }
+        if let Some(contactinfo) = self.contactinfo() {
+            context.insert("bugurl".to_string(), contactinfo.bugurl().to_string());
+        }
     }
 }
 
@@ -98,13 +218,96 @@ struct ManifestArg {
 
 struct EnvArg {
     template: path::PathBuf,
+    manifest_dir: path::PathBuf,
+    manifest: path::PathBuf,
+    local_manifest_dir: Option<path::PathBuf>,
+    manifest_url: Option<String>,
+    groups: Option<manifest::GroupFilter>,
+    submanifests: bool,
+    strict: bool,
+}
+
+struct DiffArg {
+    old: path::PathBuf,
+    new: path::PathBuf,
+    manifest_url: Option<String>,
+    json: bool,
+    strict: bool,
+}
+
+struct FlattenArg {
+    manifest_dir: path::PathBuf,
+    manifest: path::PathBuf,
+    local_manifest_dir: Option<path::PathBuf>,
+    manifest_url: Option<String>,
+    output: Option<path::PathBuf>,
+    strict: bool,
+}
+
+struct SplitArg {
+    manifest_dir: path::PathBuf,
     manifest: path::PathBuf,
+    by: manifest::SplitBy,
+    output: path::PathBuf,
+    strict: bool,
+}
+
+struct FmtArg {
+    files: Vec<path::PathBuf>,
+    check: bool,
+    sort_projects: bool,
+}
+
+enum ExportFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+struct ExportArg {
+    manifest_dir: path::PathBuf,
+    manifest: path::PathBuf,
+    local_manifest_dir: Option<path::PathBuf>,
+    manifest_url: Option<String>,
+    format: ExportFormat,
+    strict: bool,
+}
+
+struct GitmodulesArg {
+    manifest_dir: path::PathBuf,
+    manifest: path::PathBuf,
+    local_manifest_dir: Option<path::PathBuf>,
+    manifest_url: Option<String>,
+    output: path::PathBuf,
+    strict: bool,
+}
+
+struct FromGitmodulesArg {
+    gitmodules: path::PathBuf,
+    gitlinks: Option<path::PathBuf>,
+}
+
+struct FreezeArg {
+    manifest_dir: path::PathBuf,
+    manifest: path::PathBuf,
+    local_manifest_dir: Option<path::PathBuf>,
+    workspace: path::PathBuf,
+    strict: bool,
 }
 
 enum Mode {
     Projects(EnvArg),
     Remotes(EnvArg),
     Convert(ManifestArg),
+    Diff(DiffArg),
+    Freeze(FreezeArg),
+    Flatten(FlattenArg),
+    Split(SplitArg),
+    Fmt(FmtArg),
+    Export(ExportArg),
+    Schema,
+    Gitmodules(GitmodulesArg),
+    FromGitmodules(FromGitmodulesArg),
 }
 
 fn args<'a, 'b: 'a>(
@@ -143,9 +346,188 @@ fn args<'a, 'b: 'a>(
                 .help("forall projects mode")
                 .required(false),
         )
+        .arg(
+            Arg::with_name("diff")
+                .short("d")
+                .long("diff")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["OLD", "NEW"])
+                .help("compare the projects and remotes of two manifests")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "json", "yaml", "toml"])
+                .help("output format of --diff, text (the default) or json, or of --export, json (the default), yaml or toml")
+                .requires("formatted")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("freeze")
+                .short("f")
+                .long("freeze")
+                .takes_value(false)
+                .help("print the manifest with each project pinned to the commit checked out in the workspace")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("workspace")
+                .short("w")
+                .long("workspace")
+                .takes_value(true)
+                .help("top of the repo checkout used by --freeze, the parent of .repo by default")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("flatten")
+                .short("F")
+                .long("flatten")
+                .takes_value(false)
+                .help("print a single manifest with includes, local manifests and defaults resolved, like repo manifest -o")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("split")
+                .short("S")
+                .long("split")
+                .takes_value(true)
+                .possible_values(&["remote", "group"])
+                .help("split the manifest into a file per remote or group, included from a top-level manifest")
+                .requires("output")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("export")
+                .short("e")
+                .long("export")
+                .takes_value(false)
+                .help("print the resolved manifest, with effective values filled in, as JSON, YAML or TOML")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("schema")
+                .long("schema")
+                .takes_value(false)
+                .help("print the JSON Schema of the --export output")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("gitmodules")
+                .long("gitmodules")
+                .takes_value(false)
+                .help("write a .gitmodules with a submodule per project, and a script pinning them, to the --output directory")
+                .requires("output")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("from-gitmodules")
+                .long("from-gitmodules")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("print a manifest with a project per submodule of a .gitmodules file")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("gitlinks")
+                .long("gitlinks")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("with --from-gitmodules, the output of git ls-tree -r HEAD, pinning each project to its gitlink")
+                .requires("from-gitmodules")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("fmt")
+                .long("fmt")
+                .takes_value(true)
+                .multiple(true)
+                .value_name("FILE")
+                .help("rewrite manifests in canonical form, keeping comments")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .takes_value(false)
+                .help("with --fmt, list the files which are not formatted instead of rewriting them")
+                .requires("fmt")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("sort-projects")
+                .long("sort-projects")
+                .takes_value(false)
+                .help("with --fmt, also sort adjacent projects by path")
+                .requires("fmt")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("file --flatten writes to instead of stdout, or directory --split and --gitmodules write to")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("groups")
+                .short("g")
+                .long("groups")
+                .takes_value(true)
+                .help("only template the projects matching a repo style group filter, e.g. default,-notdefault")
+                .conflicts_with_all(&[
+                    "convert", "diff", "freeze", "flatten", "split", "fmt", "export", "schema",
+                    "gitmodules", "from-gitmodules",
+                ])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .takes_value(false)
+                .help("reject manifests with elements or attributes repo does not define instead of ignoring them")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("submanifests")
+                .short("s")
+                .long("submanifests")
+                .takes_value(false)
+                .help("also template the projects of every <submanifest>, with paths relative to the outer workspace")
+                .requires("projects")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("local-manifests")
+                .short("l")
+                .long("local-manifests")
+                .takes_value(false)
+                .help("overlay the local manifests found in the -M directory")
+                .conflicts_with_all(&["convert", "diff"])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("manifest-url")
+                .short("u")
+                .long("manifest-url")
+                .takes_value(true)
+                .help("URL relative remote fetch URLs are resolved against, read from the manifests.git origin by default")
+                .required(false),
+        )
+        .group(
+            clap::ArgGroup::with_name("formatted")
+                .args(&["diff", "export"])
+                .required(false),
+        )
         .group(
             clap::ArgGroup::with_name("mode")
-                .args(&["convert", "projects", "remotes"])
+                .args(&[
+                    "convert", "projects", "remotes", "diff", "freeze", "flatten", "split", "fmt",
+                    "export", "schema", "gitmodules", "from-gitmodules",
+                ])
                 .required(true),
         )
         .arg(
@@ -161,7 +543,7 @@ fn args<'a, 'b: 'a>(
                 .takes_value(true)
                 .required(false)
                 // We just want this in the help text...
-                .default_value_os(&omg)
+                .default_value_os(omg)
                 .default_value_ifs_os(&[
                     ("convert", None, &convert_dir),
                     ("remotes", None, &remote_dir),
@@ -179,7 +561,10 @@ fn args<'a, 'b: 'a>(
             Arg::with_name("manifest-dest")
                 .short("M")
                 .takes_value(true)
-                .default_value_if("convert", None, ".repo/local_manifests")
+                .default_value_ifs(&[
+                    ("convert", None, ".repo/local_manifests"),
+                    ("local-manifests", None, ".repo/local_manifests"),
+                ])
                 .required(false),
         )
         .arg(
@@ -202,6 +587,27 @@ fn args<'a, 'b: 'a>(
     app.get_matches_from_safe(env::args())
 }
 
+/// The URL given with -u, or else the origin of the manifests.git beside
+/// `manifest_dir`.
+fn manifest_url(arg: &clap::ArgMatches, manifest_dir: &path::Path) -> Option<String> {
+    arg.value_of("manifest-url")
+        .map(str::to_string)
+        .or_else(|| {
+            // .repo/manifests is a checkout of .repo/manifests.git
+            let git_dir = manifest_dir.parent()?.join("manifests.git");
+            manifest::git::origin_url(&git_dir).ok()?
+        })
+}
+
+/// Exits with a usage error for a `--format` the mode does not support.
+fn unsupported_format(format: &str, mode: &str) -> ! {
+    clap::Error::with_description(
+        &format!("--format {} is not supported by {}", format, mode),
+        clap::ErrorKind::InvalidValue,
+    )
+    .exit()
+}
+
 fn mode_for_args<'a, 'b: 'a>(config_dir: path::PathBuf, omg: &'b ffi::OsString) -> Mode {
     match args(config_dir, omg) {
         Err(err) => err.exit(),
@@ -209,16 +615,130 @@ fn mode_for_args<'a, 'b: 'a>(config_dir: path::PathBuf, omg: &'b ffi::OsString)
             let template = path::PathBuf::from(arg.value_of("template-dir").unwrap())
                 .join(arg.value_of("template-file").unwrap());
 
-            if arg.is_present("convert") {
+            if let Some(mut manifests) = arg.values_of("diff") {
+                Mode::Diff(DiffArg {
+                    old: path::PathBuf::from(manifests.next().unwrap()),
+                    new: path::PathBuf::from(manifests.next().unwrap()),
+                    manifest_url: arg.value_of("manifest-url").map(str::to_string),
+                    json: match arg.value_of("format") {
+                        None | Some("text") => false,
+                        Some("json") => true,
+                        Some(format) => unsupported_format(format, "--diff"),
+                    },
+                    strict: arg.is_present("strict"),
+                })
+            } else if arg.is_present("freeze") {
+                let manifest_dir = path::PathBuf::from(arg.value_of("manifest-dir").unwrap());
+                let workspace = match arg.value_of("workspace") {
+                    Some(workspace) => path::PathBuf::from(workspace),
+                    // .repo/manifests is two levels beneath the top of the checkout.
+                    None => manifest_dir.join("../.."),
+                };
+                Mode::Freeze(FreezeArg {
+                    manifest: manifest_dir.join(arg.value_of("manifest-file").unwrap()),
+                    local_manifest_dir: arg
+                        .value_of("manifest-dest")
+                        .filter(|_| arg.is_present("local-manifests"))
+                        .map(path::PathBuf::from),
+                    manifest_dir,
+                    workspace,
+                    strict: arg.is_present("strict"),
+                })
+            } else if arg.is_present("flatten") {
+                let manifest_dir = path::PathBuf::from(arg.value_of("manifest-dir").unwrap());
+                Mode::Flatten(FlattenArg {
+                    manifest: manifest_dir.join(arg.value_of("manifest-file").unwrap()),
+                    local_manifest_dir: arg
+                        .value_of("manifest-dest")
+                        .filter(|_| arg.is_present("local-manifests"))
+                        .map(path::PathBuf::from),
+                    manifest_url: manifest_url(&arg, &manifest_dir),
+                    manifest_dir,
+                    output: arg.value_of("output").map(path::PathBuf::from),
+                    strict: arg.is_present("strict"),
+                })
+            } else if arg.is_present("gitmodules") {
+                let manifest_dir = path::PathBuf::from(arg.value_of("manifest-dir").unwrap());
+                Mode::Gitmodules(GitmodulesArg {
+                    manifest: manifest_dir.join(arg.value_of("manifest-file").unwrap()),
+                    local_manifest_dir: arg
+                        .value_of("manifest-dest")
+                        .filter(|_| arg.is_present("local-manifests"))
+                        .map(path::PathBuf::from),
+                    manifest_url: manifest_url(&arg, &manifest_dir),
+                    manifest_dir,
+                    output: path::PathBuf::from(arg.value_of("output").unwrap()),
+                    strict: arg.is_present("strict"),
+                })
+            } else if let Some(gitmodules) = arg.value_of("from-gitmodules") {
+                Mode::FromGitmodules(FromGitmodulesArg {
+                    gitmodules: path::PathBuf::from(gitmodules),
+                    gitlinks: arg.value_of("gitlinks").map(path::PathBuf::from),
+                })
+            } else if arg.is_present("schema") {
+                Mode::Schema
+            } else if arg.is_present("export") {
+                let manifest_dir = path::PathBuf::from(arg.value_of("manifest-dir").unwrap());
+                Mode::Export(ExportArg {
+                    manifest: manifest_dir.join(arg.value_of("manifest-file").unwrap()),
+                    local_manifest_dir: arg
+                        .value_of("manifest-dest")
+                        .filter(|_| arg.is_present("local-manifests"))
+                        .map(path::PathBuf::from),
+                    manifest_url: manifest_url(&arg, &manifest_dir),
+                    manifest_dir,
+                    format: match arg.value_of("format") {
+                        None | Some("json") => ExportFormat::Json,
+                        Some("yaml") => ExportFormat::Yaml,
+                        Some("toml") => ExportFormat::Toml,
+                        Some(format) => unsupported_format(format, "--export"),
+                    },
+                    strict: arg.is_present("strict"),
+                })
+            } else if let Some(files) = arg.values_of("fmt") {
+                Mode::Fmt(FmtArg {
+                    files: files.map(path::PathBuf::from).collect(),
+                    check: arg.is_present("check"),
+                    sort_projects: arg.is_present("sort-projects"),
+                })
+            } else if let Some(by) = arg.value_of("split") {
+                let manifest_dir = path::PathBuf::from(arg.value_of("manifest-dir").unwrap());
+                Mode::Split(SplitArg {
+                    manifest: manifest_dir.join(arg.value_of("manifest-file").unwrap()),
+                    manifest_dir,
+                    by: match by {
+                        "remote" => manifest::SplitBy::Remote,
+                        _ => manifest::SplitBy::Group,
+                    },
+                    output: path::PathBuf::from(arg.value_of("output").unwrap()),
+                    strict: arg.is_present("strict"),
+                })
+            } else if arg.is_present("convert") {
                 Mode::Convert(ManifestArg {
                     template,
                     manifest_dir: path::PathBuf::from(arg.value_of("manifest-dir").unwrap()),
                     local_manifest_dir: path::PathBuf::from(arg.value_of("manifest-dest").unwrap()),
                 })
             } else {
-                let manifest = path::PathBuf::from(arg.value_of("manifest-dir").unwrap())
-                    .join(arg.value_of("manifest-file").unwrap());
-                let env_arg = EnvArg { template, manifest };
+                let manifest_dir = path::PathBuf::from(arg.value_of("manifest-dir").unwrap());
+                let manifest = manifest_dir.join(arg.value_of("manifest-file").unwrap());
+                let local_manifest_dir = if arg.is_present("local-manifests") {
+                    Some(path::PathBuf::from(arg.value_of("manifest-dest").unwrap()))
+                } else {
+                    None
+                };
+                let manifest_url = manifest_url(&arg, &manifest_dir);
+                let groups = arg.value_of("groups").map(|groups| groups.parse().unwrap());
+                let env_arg = EnvArg {
+                    template,
+                    manifest_dir,
+                    manifest,
+                    local_manifest_dir,
+                    manifest_url,
+                    groups,
+                    submanifests: arg.is_present("submanifests"),
+                    strict: arg.is_present("strict"),
+                };
                 if arg.is_present("remotes") {
                     Mode::Remotes(env_arg)
                 } else {
@@ -229,45 +749,321 @@ fn mode_for_args<'a, 'b: 'a>(config_dir: path::PathBuf, omg: &'b ffi::OsString)
     }
 }
 
-fn projects_cmd(arg: EnvArg) -> Result<(), Error> {
-    let mut template = String::new();
-    fs::File::open(arg.template)?.read_to_string(&mut template)?;
-    let default_file = fs::File::open(arg.manifest)?;
-    let default_file = io::BufReader::new(default_file);
-    let mut manifest: Manifest = manifest::de::from_reader(default_file)?;
-    manifest.set_defaults();
+fn load_resolved(
+    manifest_dir: &path::Path,
+    manifest: &path::Path,
+    local_manifest_dir: Option<&path::Path>,
+    manifest_url: Option<&str>,
+    strict: bool,
+) -> Result<Manifest, Error> {
+    let mut manifest = if let Some(local_manifest_dir) = local_manifest_dir {
+        manifest::load_with_local_manifests(manifest_dir, manifest, local_manifest_dir)?
+    } else {
+        manifest::load(manifest_dir, manifest)?
+    };
+    check_strict(&manifest, strict)?;
+    if let Some(manifest_url) = manifest_url {
+        manifest.resolve_fetch_urls(manifest_url);
+    }
+    Ok(manifest)
+}
+
+/// With `strict`, rejects elements and attributes repo does not define.
+fn check_strict(manifest: &Manifest, strict: bool) -> Result<(), Error> {
+    let unknowns = manifest.unknowns();
+    if strict && !unknowns.is_empty() {
+        return Err(Error::Strict(unknowns.into_iter().cloned().collect()));
+    }
+    Ok(())
+}
+
+/// Rejects a manifest repo would refuse, printing lesser problems as warnings.
+fn check_valid(manifest: &Manifest) -> Result<(), Error> {
+    let (errors, warnings): (Vec<_>, Vec<_>) = manifest
+        .validate()
+        .into_iter()
+        .partition(manifest::ValidationError::is_fatal);
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Invalid(errors))
+    }
+}
+
+fn load_manifest(arg: &EnvArg) -> Result<Manifest, Error> {
+    let manifest = load_resolved(
+        &arg.manifest_dir,
+        &arg.manifest,
+        arg.local_manifest_dir.as_deref(),
+        arg.manifest_url.as_deref(),
+        arg.strict,
+    )?;
+    check_valid(&manifest)?;
+    Ok(manifest)
+}
+
+fn template_projects(
+    manifest: &Manifest,
+    submanifest_path: &str,
+    template: &str,
+    groups: Option<&manifest::GroupFilter>,
+    output: &mut dyn io::Write,
+) -> Result<(), Error> {
     let mut remote_hash = HashMap::new();
     manifest.remotes().iter().for_each(|remote| {
         remote_hash.insert(remote.name().to_string(), remote);
     });
 
-    let mut stdout = io::BufWriter::new(io::stdout());
-    for project in manifest.projects() {
+    let projects = match groups {
+        Some(groups) => manifest.projects_in_groups(groups),
+        None => manifest.projects().iter().collect(),
+    };
+
+    for project in projects {
         let mut context: HashMap<String, String> = HashMap::new();
+        manifest.into_hash(&mut context);
+        context.insert("submanifest_path".to_string(), submanifest_path.to_string());
         if let Some(remote_name) = project.remote() {
             if let Some(remote) = remote_hash.get(remote_name) {
                 remote.into_hash(&mut context);
             }
         }
-        context.insert("project_name".to_string(), project.name().to_string());
-        envsubst_write(&template, &mut stdout, &context)?;
+        project.into_hash(&mut context);
+        if let Some(clone_url) = manifest.clone_url(project, None) {
+            context.insert("clone_url".to_string(), clone_url);
+        }
+        envsubst_write(template, output, &context)?;
     }
-    return Ok(());
+    Ok(())
+}
+
+fn projects_cmd(arg: EnvArg) -> Result<(), Error> {
+    let mut template = String::new();
+    fs::File::open(&arg.template)?.read_to_string(&mut template)?;
+    let mut manifest = load_manifest(&arg)?;
+    manifest.set_defaults();
+    let mut stdout = io::BufWriter::new(io::stdout());
+    template_projects(&manifest, "", &template, arg.groups.as_ref(), &mut stdout)?;
+
+    if arg.submanifests {
+        // .repo/manifests is a checkout within .repo
+        let repo_dir = arg.manifest_dir.join("..");
+        for submanifest in manifest::load_submanifests(&repo_dir, &manifest)? {
+            let (path, sub_repo_dir, mut manifest) = submanifest.dissolve();
+            check_strict(&manifest, arg.strict)?;
+            check_valid(&manifest)?;
+            let git_dir = sub_repo_dir.join("manifests.git");
+            if let Ok(Some(manifest_url)) = manifest::git::origin_url(&git_dir) {
+                manifest.resolve_fetch_urls(&manifest_url);
+            }
+            manifest.set_defaults();
+            template_projects(
+                &manifest,
+                &path,
+                &template,
+                arg.groups.as_ref(),
+                &mut stdout,
+            )?;
+        }
+    }
+    Ok(())
 }
 
 fn remotes_cmd(arg: EnvArg) -> Result<(), Error> {
     let mut template = String::new();
-    fs::File::open(arg.template)?.read_to_string(&mut template)?;
+    fs::File::open(&arg.template)?.read_to_string(&mut template)?;
     let mut stdout = io::BufWriter::new(io::stdout());
-    let mut context: HashMap<String, String> = HashMap::new();
-    let manifest_file = fs::File::open(arg.manifest)?;
-    let manifest_file = io::BufReader::new(manifest_file);
-    let manifest: Manifest = manifest::de::from_reader(manifest_file)?;
+    let mut manifest = load_manifest(&arg)?;
+    manifest.set_defaults();
+    // With --groups, only the remotes of the selected projects.
+    let selected: Option<Vec<&String>> = arg.groups.as_ref().map(|groups| {
+        manifest
+            .projects_in_groups(groups)
+            .into_iter()
+            .filter_map(|project| project.remote().as_ref())
+            .collect()
+    });
     for remote in manifest.remotes() {
+        if let Some(selected) = &selected {
+            if !selected.contains(&remote.name()) {
+                continue;
+            }
+        }
+        let mut context: HashMap<String, String> = HashMap::new();
+        manifest.into_hash(&mut context);
         remote.into_hash(&mut context);
         envsubst_write(&template, &mut stdout, &context)?;
     }
-    return Ok(())
+    Ok(())
+}
+
+fn diff_cmd(arg: DiffArg) -> Result<(), Error> {
+    let mut manifests = Vec::new();
+    for manifest in [&arg.old, &arg.new].iter() {
+        // Includes are relative to the directory of each manifest.
+        let manifest_dir = manifest.parent().unwrap_or_else(|| path::Path::new(""));
+        let mut manifest = load_resolved(
+            manifest_dir,
+            manifest,
+            None,
+            arg.manifest_url.as_deref(),
+            arg.strict,
+        )?;
+        manifest.set_defaults();
+        manifests.push(manifest);
+    }
+    let changes = manifests[0].diff(&manifests[1]);
+    let mut stdout = io::BufWriter::new(io::stdout());
+    if arg.json {
+        serde_json::to_writer_pretty(&mut stdout, &changes)?;
+        writeln!(stdout)?;
+    } else {
+        for change in changes {
+            writeln!(stdout, "{}", change)?;
+        }
+    }
+    Ok(())
+}
+
+fn freeze_cmd(arg: FreezeArg) -> Result<(), Error> {
+    let mut manifest = load_resolved(
+        &arg.manifest_dir,
+        &arg.manifest,
+        arg.local_manifest_dir.as_deref(),
+        None,
+        arg.strict,
+    )?;
+    manifest.freeze(&arg.workspace)?;
+    let mut stdout = io::BufWriter::new(io::stdout());
+    write!(stdout, "{}", manifest.to_xml()?)?;
+    Ok(())
+}
+
+fn flatten_cmd(arg: FlattenArg) -> Result<(), Error> {
+    let mut manifest = load_resolved(
+        &arg.manifest_dir,
+        &arg.manifest,
+        arg.local_manifest_dir.as_deref(),
+        arg.manifest_url.as_deref(),
+        arg.strict,
+    )?;
+    manifest.set_defaults();
+    let xml = manifest.to_xml()?;
+    match arg.output {
+        Some(output) => fs::write(output, xml)?,
+        None => write!(io::stdout(), "{}", xml)?,
+    }
+    Ok(())
+}
+
+fn split_cmd(arg: SplitArg) -> Result<(), Error> {
+    let manifest = load_resolved(&arg.manifest_dir, &arg.manifest, None, None, arg.strict)?;
+    let dir = match arg.by {
+        manifest::SplitBy::Remote => "remotes",
+        manifest::SplitBy::Group => "groups",
+    };
+    let (top, parts) = manifest.split(arg.by, dir);
+    fs::create_dir_all(arg.output.join(dir))?;
+    let file_name = arg
+        .manifest
+        .file_name()
+        .unwrap_or_else(|| "default.xml".as_ref());
+    fs::write(arg.output.join(file_name), top.to_xml()?)?;
+    for (name, part) in parts {
+        fs::write(arg.output.join(name), part.to_xml()?)?;
+    }
+    Ok(())
+}
+
+/// Formats `file`, or with `check` leaves it alone, returning whether it was
+/// already formatted.
+fn fmt_file(file: &path::Path, check: bool, sort_projects: bool) -> Result<bool, Error> {
+    let source = fs::read_to_string(file)?;
+    let formatted = manifest::format_manifest(&source, sort_projects)?;
+    if formatted == source {
+        return Ok(true);
+    }
+    if !check {
+        fs::write(file, formatted)?;
+    }
+    Ok(false)
+}
+
+fn fmt_cmd(arg: FmtArg) -> Result<(), Error> {
+    let mut errors = Vec::new();
+    let mut unformatted = Vec::new();
+    for file in arg.files {
+        match fmt_file(&file, arg.check, arg.sort_projects) {
+            Ok(true) => (),
+            Ok(false) if arg.check => unformatted.push(file),
+            Ok(false) => (),
+            Err(err) => errors.push(Error::File(file, Box::new(err))),
+        }
+    }
+    if !unformatted.is_empty() {
+        errors.push(Error::Unformatted(unformatted));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Multiple(errors))
+    }
+}
+
+fn export_cmd(arg: ExportArg) -> Result<(), Error> {
+    let mut manifest = load_resolved(
+        &arg.manifest_dir,
+        &arg.manifest,
+        arg.local_manifest_dir.as_deref(),
+        arg.manifest_url.as_deref(),
+        arg.strict,
+    )?;
+    manifest.set_defaults();
+    let mut stdout = io::BufWriter::new(io::stdout());
+    match arg.format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, &manifest)?;
+            writeln!(stdout)?;
+        }
+        ExportFormat::Yaml => serde_yaml_ng::to_writer(&mut stdout, &manifest)?,
+        ExportFormat::Toml => write!(stdout, "{}", toml::to_string(&manifest)?)?,
+    }
+    Ok(())
+}
+
+fn gitmodules_cmd(arg: GitmodulesArg) -> Result<(), Error> {
+    let mut manifest = load_resolved(
+        &arg.manifest_dir,
+        &arg.manifest,
+        arg.local_manifest_dir.as_deref(),
+        arg.manifest_url.as_deref(),
+        arg.strict,
+    )?;
+    manifest.set_defaults();
+    let submodules = manifest.submodules(None)?;
+    fs::create_dir_all(&arg.output)?;
+    fs::write(
+        arg.output.join(".gitmodules"),
+        manifest::write_gitmodules(&submodules),
+    )?;
+    fs::write(
+        arg.output.join("pin-submodules.sh"),
+        manifest::pin_script(&submodules),
+    )?;
+    Ok(())
+}
+
+fn from_gitmodules_cmd(arg: FromGitmodulesArg) -> Result<(), Error> {
+    let mut submodules = manifest::parse_gitmodules(&fs::read_to_string(&arg.gitmodules)?)?;
+    if let Some(gitlinks) = arg.gitlinks {
+        manifest::apply_gitlinks(&mut submodules, &fs::read_to_string(gitlinks)?);
+    }
+    let manifest = Manifest::from_submodules(&submodules);
+    write!(io::stdout(), "{}", manifest.to_xml()?)?;
+    Ok(())
 }
 
 fn convert_cmd(arg: ManifestArg) -> Result<(), Error> {
@@ -281,11 +1077,9 @@ fn convert_cmd(arg: ManifestArg) -> Result<(), Error> {
                 .extension()
                 .and_then(ffi::OsStr::to_str);
             if extension == Some("xml") {
-                let file = io::BufReader::new(
-                    fs::File::open(dir_entry.path())
-                        .or(Err(Error::FileNotFound(Box::new(dir_entry.path()))))?,
-                );
-                let manifest: Manifest = manifest::de::from_reader(file)?;
+                let source = fs::read_to_string(dir_entry.path())
+                    .or(Err(Error::FileNotFound(Box::new(dir_entry.path()))))?;
+                let manifest: Manifest = manifest::parse_str(&source, &dir_entry.path())?;
                 fs::create_dir_all(arg.local_manifest_dir.clone())?;
                 let local_manifest_path = arg.local_manifest_dir.clone().join(file_name);
                 let mut local_manifest_file = fs::File::create(local_manifest_path)?;
@@ -298,34 +1092,29 @@ fn convert_cmd(arg: ManifestArg) -> Result<(), Error> {
                     let config = read_dot_env(io::BufReader::new(config_subst.as_bytes()))?;
 
                     if let Some(fetch_url) = config.get("fetch_url") {
-                        let local_remote = manifest::Remote::new(
-                            name.clone(),
-                            None,
-                            config.get("push_url").cloned(),
-                            fetch_url.to_string(),
-                            config.get("review_url").cloned(),
-                            None,
-                            config
-                                .get("review_protocol")
-                                .map(|s| manifest::ReviewProtocolType::from_str(s).unwrap()),
-                            Some(true),
-                        );
-                        remotes.push(local_remote);
+                        let mut local_remote = manifest::RemoteBuilder::default()
+                            .name(name.clone())
+                            .fetch(fetch_url.clone())
+                            .r#override(true);
+                        if let Some(push_url) = config.get("push_url") {
+                            local_remote = local_remote.pushurl(push_url.clone());
+                        }
+                        if let Some(review_url) = config.get("review_url") {
+                            local_remote = local_remote.review(review_url.clone());
+                        }
+                        if let Some(protocol) = config.get("review_protocol") {
+                            local_remote = local_remote
+                                .r#type(manifest::ReviewProtocolType::from_str(protocol).unwrap());
+                        }
+                        remotes.push(local_remote.build().unwrap());
                     } else {
                         return Err(Error::FetchRequired);
                     }
                 }
-                let manifest: Manifest = Manifest::new(
-                    None,
-                    None,
-                    remotes,
-                    None,
-                    vec![],
-                    vec![],
-                    vec![],
-                    None,
-                    vec![],
-                );
+                let manifest = manifest::ManifestBuilder::default()
+                    .remotes(remotes)
+                    .build()
+                    .unwrap();
                 let writer = qxml::Writer::new_with_indent(&mut local_manifest_file, b'\t', 1);
                 let mut ser = manifest::se::Serializer::with_root(writer, None);
                 manifest.serialize(&mut ser)?;
@@ -336,7 +1125,7 @@ fn convert_cmd(arg: ManifestArg) -> Result<(), Error> {
     Ok(())
 }
 
-fn main() -> Result<(), Error> {
+fn run() -> Result<(), Error> {
     if let Some(config_dir) = dirs::config_dir() {
         let omg = config_dir.join("<mode>").into_os_string();
         match mode_for_args(config_dir, &omg) {
@@ -345,8 +1134,36 @@ fn main() -> Result<(), Error> {
             Mode::Remotes(arg) => remotes_cmd(arg),
 
             Mode::Convert(arg) => convert_cmd(arg),
+
+            Mode::Diff(arg) => diff_cmd(arg),
+
+            Mode::Freeze(arg) => freeze_cmd(arg),
+
+            Mode::Flatten(arg) => flatten_cmd(arg),
+
+            Mode::Split(arg) => split_cmd(arg),
+
+            Mode::Fmt(arg) => fmt_cmd(arg),
+
+            Mode::Export(arg) => export_cmd(arg),
+
+            Mode::Schema => {
+                print!("{}", manifest::JSON_SCHEMA);
+                Ok(())
+            }
+
+            Mode::Gitmodules(arg) => gitmodules_cmd(arg),
+
+            Mode::FromGitmodules(arg) => from_gitmodules_cmd(arg),
         }
     } else {
         Err(Error::UnknownConfigPath)
     }
 }
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}